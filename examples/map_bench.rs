@@ -0,0 +1,79 @@
+//! Measures how much memory the map takes and how long generating and drawing frames takes,
+//! next to the way tiles used to be stored and looked up.
+//!
+//! Run with `cargo run --release --example map_bench`.
+
+use std::hint::black_box;
+use std::mem;
+use std::time::{Duration, Instant};
+use rust_game::{render, Command, Game, GameConfig, MemorySurface, Position, Tile, TileType, CHUNK_SIZE};
+
+const FRAMES: u32 = 200;
+
+// How far around the camera rendering on a 200x60 surface looks
+const VIEW_DISTANCE: i32 = 100;
+
+// How tiles were stored before they were packed: each one knew its own position
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+struct UnpackedTile {
+    tile_type: TileType,
+    variant: usize,
+    position: Position
+}
+
+fn per_frame(total: Duration) -> f64 {
+    total.as_secs_f64() * 1000.0 / FRAMES as f64
+}
+
+// What rendering started with before map views: a copy of every tile around the camera,
+// looked up one by one.
+fn portion_around(game: &Game, pos: &Position, distance: i32) -> Vec<UnpackedTile> {
+    let mut portion = Vec::new();
+    for x in pos.x() - distance..pos.x() + distance {
+        for y in pos.y() - distance..pos.y() + distance {
+            if let Some(tile) = game.map().at(x, y) {
+                portion.push(UnpackedTile { tile_type: tile.tile_type(), variant: tile.variant() as usize, position: Position::new(x, y) });
+            }
+        }
+    }
+    portion
+}
+
+fn main() {
+    let start = Instant::now();
+    let mut game = Game::new(&GameConfig::default()).expect("the default config is valid");
+    println!("world generation: {:.1} ms", start.elapsed().as_secs_f64() * 1000.0);
+    let tiles = game.map().loaded_chunks() * (CHUNK_SIZE * CHUNK_SIZE) as usize;
+    println!("tile size: {} bytes (unpacked: {} bytes)", mem::size_of::<Tile>(), mem::size_of::<UnpackedTile>());
+    println!("loaded tiles: {} ({} KiB, unpacked: {} KiB)", tiles, tiles * mem::size_of::<Tile>() / 1024,
+        tiles * mem::size_of::<UnpackedTile>() / 1024);
+
+    let camera = *game.camera_position();
+    let start = Instant::now();
+    let mut seen = 0;
+    for _ in 0..FRAMES {
+        seen += black_box(game.map().view_around(&camera, VIEW_DISTANCE).iter().count());
+    }
+    let borrowed = per_frame(start.elapsed());
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        black_box(portion_around(&game, &camera, VIEW_DISTANCE));
+    }
+    let copied = per_frame(start.elapsed());
+    println!("{} tiles around the camera: {:.3} ms per frame (copied: {:.3} ms)", seen / FRAMES as usize, borrowed, copied);
+
+    let mut surface = MemorySurface::new(200, 60);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        render(&mut surface, &game);
+    }
+    println!("render: {:.3} ms per frame", per_frame(start.elapsed()));
+
+    let start = Instant::now();
+    for i in 0..FRAMES {
+        game.step(if i % 2 == 0 { Command::Move(1, 0) } else { Command::Move(-1, 0) });
+        render(&mut surface, &game);
+    }
+    println!("step and render: {:.3} ms per frame", per_frame(start.elapsed()));
+}
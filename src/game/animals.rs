@@ -1,86 +1,167 @@
 use rand::prelude::*;
-use super::Entity;
+use serde::{Serialize, Deserialize};
+use super::Game;
+use super::components::{Ai, Name, WantsToMove};
 use super::position::*;
+use super::registry::EntityId;
 use super::render::RenderInfo;
 use super::rpg::CharacterStats;
-use rustbox::Color;
+use super::pathfinding::{DijkstraMap, NEIGHBORS};
+use super::surface::Color;
+use super::world::EntityBuilder;
 
-#[derive(Copy, Clone, PartialEq)]
+// How many steps of being out of the player's sight are worth when picking where to flee
+const COVER_BONUS: i32 = 3;
+
+/// What an animal is up to.
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AnimalState {
+    /// Wandering around.
     Idle,
+    /// Running away after seeing the player.
     FleeFromPlayer
 }
 
-#[derive(Clone)]
-pub struct Animal {
-    position: Position,
-    state: AnimalState,
-    render_info: RenderInfo,
-    character_stats: CharacterStats
+/// Wild animal that wanders around and flees from the player.
+pub fn animal(position: Position) -> EntityBuilder {
+    EntityBuilder::new()
+        .at(position)
+        .with_render_info(RenderInfo::new('a', Color::Cyan))
+        .with_name(Name::new(None, "Animal"))
+        .with_stats(CharacterStats::new(3, 10, 15))
+        .with_ai(Ai::Animal(AnimalState::Idle))
+        .with_vision()
+        .with_energy()
 }
 
-impl Animal {
-    pub fn new(x: i32, y: i32) -> Animal {
-        Animal {
-            character_stats: CharacterStats::new(3, 10, 15),
-            position: Position::new(x, y),
-            state: AnimalState::Idle,
-            render_info: RenderInfo::new('a', Color::Cyan)
+/// Decides where the animal `id` goes this tick and whether it keeps fleeing.
+pub fn think(game: &mut Game, id: EntityId, state: AnimalState) {
+    let position = match game.world.position(id) {
+        Some(position) => *position,
+        None => return
+    };
+    let player_position = *game.player_position();
+    let sees_player = game.world.can_see(id, &player_position);
+    let (state, color) = match state {
+        AnimalState::Idle => {
+            if game.rng.gen_bool(0.3) {
+                // Move randomly, sometimes.
+                let mut destination = position;
+                destination.move_relative(game.rng.gen_range(-1, 2), game.rng.gen_range(-1, 2));
+                game.world.wants_to_move.insert(id, WantsToMove(destination));
+            }
+            // If we see the player we flee
+            let state = if sees_player { AnimalState::FleeFromPlayer } else { AnimalState::Idle };
+            (state, Color::Cyan)
+        },
+        AnimalState::FleeFromPlayer => {
+            let mut state = AnimalState::FleeFromPlayer;
+            if sees_player {
+                if let Some(step) = flee_step(game, id, &position) {
+                    game.world.wants_to_move.insert(id, WantsToMove(step));
+                }
+            } else if game.rng.gen_bool(0.3) {
+                // If we don't see it anymore, maybe we stop fleeing
+                state = AnimalState::Idle;
+            }
+            (state, Color::Red)
         }
+    };
+    game.world.ai.insert(id, Ai::Animal(state));
+    if let Some(render_info) = game.world.render_info.get_mut(id) {
+        render_info.color = color;
     }
 }
 
-impl Entity for Animal {
-    fn position(&mut self) -> &mut Position {
-        &mut self.position
+// Where fleeing from the player is safest: the step that gets furthest away while leaving a
+// way out, better yet behind something the player can't see through. `None` means staying.
+fn flee_step(game: &Game, id: EntityId, position: &Position) -> Option<Position> {
+    let open_ground = |position: &Position| game.map.at(position.x(), position.y()).is_some_and(|tile| tile.is_passable());
+    let radius = game.world.stats.get(id).map_or(0, |stats| stats.per.lvl() as i32);
+    let threats = DijkstraMap::new(&[*game.player_position()], position, radius, open_ground);
+    let flee = threats.flee(open_ground);
+    let danger = |position: &Position| flee.distance(position).map(|distance| {
+        if game.world.can_see(game.player, position) { distance } else { distance - COVER_BONUS }
+    });
+    let mut best = (danger(position).unwrap_or(i32::MAX), None);
+    for (dx, dy) in &NEIGHBORS {
+        let next = Position::new(position.x() + dx, position.y() + dy);
+        if let Some(distance) = danger(&next) {
+            if distance < best.0 && game.is_passable(&next) {
+                best = (distance, Some(next));
+            }
+        }
     }
+    best.1
+}
 
-    fn current_position(&self) -> &Position {
-        &self.position
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::GameConfig;
+    use super::super::map::{Tile, TileType};
+    use super::super::systems;
 
-    fn render_info(&self) -> &RenderInfo {
-        &self.render_info
-    }
+    // The animal is backed against a wall by the player. Straight away from the player is a dead
+    // end, the way out is through the gap above it into the room.
+    const CORNERED: &[&str] = &[
+        "###############",
+        "#.............#",
+        "#.............#",
+        "#.............#",
+        "###.###########",
+        "#..A..P.......#",
+        "###############"
+    ];
 
-    fn name(&self) -> Option<&str> {
-        None
+    // Lays `rows` out around the player standing on 'P', and returns where 'A' is
+    fn lay_out(game: &mut Game, rows: &[&str]) -> Position {
+        let player = *game.player_position();
+        let origin = rows.iter().enumerate()
+            .find_map(|(y, row)| row.find('P').map(|x| (player.x() - x as i32, player.y() - y as i32)))
+            .unwrap();
+        let mut marked = None;
+        for (y, row) in rows.iter().enumerate() {
+            for (x, tile) in row.chars().enumerate() {
+                let position = Position::new(origin.0 + x as i32, origin.1 + y as i32);
+                let tile_type = if tile == '#' { TileType::StoneWall } else { TileType::Floor };
+                game.map.replace_tile(position.x(), position.y(), Tile::new(tile_type, 0));
+                if tile == 'A' {
+                    marked = Some(position);
+                }
+            }
+        }
+        marked.unwrap()
     }
 
-    fn kind(&self) -> &str {
-        "Animal"
+    fn cornered() -> (Game, EntityId, Position) {
+        let config = GameConfig { seed: 7, map_size: Some(64), animals: 0, spawn_radius: 5, ..GameConfig::default() };
+        let mut game = Game::new(&config).unwrap();
+        let position = lay_out(&mut game, CORNERED);
+        let id = game.world.spawn(animal(position).with_ai(Ai::Animal(AnimalState::FleeFromPlayer)));
+        game.update_surroundings();
+        (game, id, position)
     }
 
-    fn stats(&self) -> &CharacterStats {
-        &self.character_stats
+    #[test]
+    fn cornered_animal_flees_around_the_wall() {
+        let (game, id, position) = cornered();
+        let player = *game.player_position();
+        let step = flee_step(&game, id, &position).unwrap();
+        assert_eq!(step, Position::new(position.x(), position.y() - 1));
+        // Not into the dead end, even though it is further from the player
+        let dead_end = Position::new(position.x() - 1, position.y());
+        assert!(dead_end.distance_from(&player) > step.distance_from(&player));
     }
 
-    fn tick(&mut self, game: &mut super::Game) {
-        match self.state {
-            AnimalState::Idle => {
-                self.render_info.color = Color::Cyan;
-                if game.rng.gen_bool(0.3) {
-                    // Move randomly, sometimes.
-                    self.position.move_relative_if_passable(game.rng.gen_range(-1, 2), game.rng.gen_range(-1, 2), game);
-                }
-                if self.can_see(&game.player.current_position()) {
-                    // If we see the player we flee
-                    self.state = AnimalState::FleeFromPlayer;
-                } 
-            },
-            AnimalState::FleeFromPlayer => {
-                self.render_info.color = Color::Red;
-                if self.can_see(&game.player.current_position()) {
-                    // If we see the player we flee
-                    let dir = self.current_position().direction_for(game.player_position());
-                    self.position.move_relative_if_passable(-1 * dir.0, -1 * dir.1, game);
-                } else {
-                    // If we don't see it anymore, maybe we stop fleeing
-                    if game.rng.gen_bool(0.3) {
-                        self.state = AnimalState::Idle;
-                    }
-                }
-            }
-        }
+    #[test]
+    fn fleeing_animal_wants_to_take_the_step() {
+        let (mut game, id, position) = cornered();
+        // It only flees from a player it can see
+        systems::vision(&mut game.world, &game.map, id);
+        think(&mut game, id, AnimalState::FleeFromPlayer);
+        let WantsToMove(destination) = *game.world.wants_to_move.get(id).unwrap();
+        assert_eq!(destination, Position::new(position.x(), position.y() - 1));
+        assert!(destination != *game.player_position());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,20 @@
+use serde::{Serialize, Deserialize};
+
+/// An action requested by the player, independent of the input device.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Command {
+    /// Steps by the given horizontal and vertical offsets, or moves the cursor while inspecting.
+    Move(i32, i32),
+    /// Lets a turn go by.
+    Wait,
+    /// Takes the stairs down, when standing on them.
+    Descend,
+    /// Takes the stairs up, when standing on them.
+    Ascend,
+    /// Starts or stops inspecting tiles with the cursor.
+    ToggleInspect,
+    /// Stops inspecting tiles, or quits during gameplay.
+    Cancel,
+    /// Ends the game.
+    Quit
+}
@@ -0,0 +1,61 @@
+use serde::{Serialize, Deserialize};
+use super::animals::AnimalState;
+use super::fov::FieldOfView;
+use super::position::Position;
+use super::world::EntityBuilder;
+
+/// What an entity is called, if anything, and what it is.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Name {
+    /// Proper name, for those who have one.
+    pub name: Option<String>,
+    /// What kind of entity it is, like "Animal".
+    pub kind: String
+}
+
+impl Name {
+    /// A name of the given kind, copying the strings.
+    pub fn new(name: Option<&str>, kind: &str) -> Name {
+        Name { name: name.map(str::to_string), kind: kind.to_string() }
+    }
+}
+
+/// Looks around with a field of view as far as its perception reaches, opaque tiles blocking
+/// the way. Entities without it see everything within perception range.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Vision {
+    /// Tiles seen during the last tick, recomputed instead of saved.
+    #[serde(skip)]
+    pub field_of_view: FieldOfView
+}
+
+/// How an entity decides what to do on its own.
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Ai {
+    /// Wanders around and flees from the player.
+    Animal(AnimalState)
+}
+
+/// Can be picked up and carried instead of standing in the way.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Item {}
+
+/// Items carried by an entity, with every component they had on the ground.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Inventory {
+    /// Carried items, in the order they were picked up.
+    pub items: Vec<EntityBuilder>
+}
+
+/// Energy an action takes, whether it's a step or waiting around.
+pub const ACTION_COST: i32 = 100;
+
+/// Energy gathered towards the next action, as fast as the entity's speed. Entities with an
+/// [`Ai`] but no energy act once per turn of the player.
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+pub struct Energy(pub i32);
+
+/// Where an entity decided to step this tick. The movement system clears it, moving the
+/// entity if nothing stands there by then.
+#[derive(Copy, Clone)]
+pub struct WantsToMove(pub Position);
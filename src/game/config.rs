@@ -0,0 +1,93 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use super::dungeon::{MapType, MIN_DUNGEON_SIZE};
+
+/// Parameters of a new world. Without a `map_size` the overworld has no edges and dungeons
+/// get a default size.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameConfig {
+    /// Seeds everything random, so the same config always produces the same world.
+    pub seed: u64,
+    /// Side of the map in tiles, if it has edges.
+    pub map_size: Option<usize>,
+    /// Overworld or the kind of dungeon to generate.
+    // Missing from replays recorded before dungeons existed
+    #[serde(default)]
+    pub map_type: MapType,
+    /// How many animals to spawn around the start.
+    pub animals: usize,
+    /// How far from the start animals may spawn.
+    pub spawn_radius: i32
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            seed: 1234,
+            map_size: None,
+            map_type: MapType::Overworld,
+            animals: 10,
+            spawn_radius: 50
+        }
+    }
+}
+
+impl GameConfig {
+    /// Checks that a world can be generated from the config.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.map_size == Some(0) {
+            return Err(ConfigError::EmptyMap)
+        }
+        if self.spawn_radius <= 0 {
+            return Err(ConfigError::SpawnRadius(self.spawn_radius))
+        }
+        match self.map_size {
+            Some(size) if self.map_type != MapType::Overworld && size < MIN_DUNGEON_SIZE => {
+                Err(ConfigError::DungeonTooSmall(size))
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+/// Why no world can be generated from a [`GameConfig`].
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// The map size is zero.
+    EmptyMap,
+    /// The spawn radius isn't greater than zero.
+    SpawnRadius(i32),
+    /// The map size is below [`MIN_DUNGEON_SIZE`] for a dungeon.
+    DungeonTooSmall(usize)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::EmptyMap => write!(f, "map size must be greater than zero"),
+            ConfigError::SpawnRadius(radius) => write!(f, "spawn radius must be greater than zero, not {}", radius),
+            ConfigError::DungeonTooSmall(size) => {
+                write!(f, "map size must be at least {} for dungeons, not {}", MIN_DUNGEON_SIZE, size)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Game;
+
+    #[test]
+    fn configs_that_cant_generate_a_world_are_rejected() {
+        let config = |map_type, map_size, spawn_radius| GameConfig { map_type, map_size, spawn_radius, ..GameConfig::default() };
+        assert_eq!(GameConfig::default().validate(), Ok(()));
+        assert_eq!(config(MapType::Overworld, Some(0), 5).validate(), Err(ConfigError::EmptyMap));
+        assert_eq!(config(MapType::Overworld, None, 0).validate(), Err(ConfigError::SpawnRadius(0)));
+        assert_eq!(config(MapType::Overworld, Some(8), 5).validate(), Ok(()));
+        assert_eq!(config(MapType::Rooms, Some(MIN_DUNGEON_SIZE - 1), 5).validate(), Err(ConfigError::DungeonTooSmall(MIN_DUNGEON_SIZE - 1)));
+        assert_eq!(config(MapType::Caves, Some(MIN_DUNGEON_SIZE), 5).validate(), Ok(()));
+        assert!(matches!(Game::new(&config(MapType::Overworld, None, 0)), Err(ConfigError::SpawnRadius(0))));
+        assert!(matches!(Game::new(&config(MapType::Rooms, Some(0), 5)), Err(ConfigError::EmptyMap)));
+    }
+}
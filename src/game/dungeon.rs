@@ -0,0 +1,446 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use super::GameRng;
+use super::map::{GameMap, Tile, TileType};
+use super::position::Position;
+
+/// Dungeons smaller than this don't leave room for the generators to work with.
+pub const MIN_DUNGEON_SIZE: usize = 16;
+
+/// Width and height of a dungeon when the config doesn't give a map size.
+pub const DEFAULT_DUNGEON_SIZE: usize = 100;
+
+// Smallest area the rooms generator keeps splitting
+const MIN_LEAF_SIZE: i32 = 10;
+
+const ORTHOGONAL: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// Which generator lays out a new map.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum MapType {
+    /// Open terrain with biomes, unbounded unless given a size.
+    #[default]
+    Overworld,
+    /// Rooms in a binary space partition, joined by corridors with doors.
+    Rooms,
+    /// Caves grown by a cellular automaton.
+    Caves,
+    /// Winding tunnels dug by a random walk.
+    Tunnels
+}
+
+impl MapType {
+    /// Name used on the command line and in messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapType::Overworld => "overworld",
+            MapType::Rooms => "rooms",
+            MapType::Caves => "caves",
+            MapType::Tunnels => "tunnels"
+        }
+    }
+
+    /// Every map type, the overworld first.
+    pub fn all() -> &'static [MapType] {
+        &[MapType::Overworld, MapType::Rooms, MapType::Caves, MapType::Tunnels]
+    }
+
+    /// Size used when the config leaves it open: dungeons always have edges.
+    pub fn default_size(&self) -> Option<usize> {
+        match self {
+            MapType::Overworld => None,
+            _ => Some(DEFAULT_DUNGEON_SIZE)
+        }
+    }
+}
+
+impl fmt::Display for MapType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MapType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<MapType, String> {
+        MapType::all().iter().find(|map_type| map_type.name() == name).copied()
+            .ok_or_else(|| format!("unknown map type '{}'", name))
+    }
+}
+
+/// Walls, floors and doors laid out by one of the dungeon generators, ready to be stamped on a
+/// [`GameMap`]. Every floor and door can be reached from the spawn point.
+pub struct Dungeon {
+    size: i32,
+    tiles: Vec<TileType>,
+    spawn_point: Position
+}
+
+impl Dungeon {
+    /// Lays out a `size` by `size` dungeon. Fails for the overworld, which isn't a dungeon, and
+    /// for sizes below [`MIN_DUNGEON_SIZE`].
+    pub fn generate(map_type: MapType, size: usize, rng: &mut GameRng) -> Result<Dungeon, String> {
+        if map_type == MapType::Overworld {
+            return Err("the overworld isn't a dungeon".to_string())
+        }
+        if size < MIN_DUNGEON_SIZE {
+            return Err(format!("dungeons must be at least {} tiles wide, not {}", MIN_DUNGEON_SIZE, size))
+        }
+        let size = size as i32;
+        let mut dungeon = Dungeon {
+            size,
+            tiles: vec![TileType::StoneWall; (size * size) as usize],
+            spawn_point: Position::new(size / 2, size / 2)
+        };
+        match map_type {
+            MapType::Rooms => {
+                dungeon.split_rooms(1, 1, size - 2, size - 2, rng);
+                dungeon.place_doors(rng);
+            },
+            MapType::Caves => dungeon.grow_caves(rng),
+            MapType::Tunnels => dungeon.dig_tunnels(rng),
+            MapType::Overworld => unreachable!("rejected above")
+        }
+        dungeon.connect_regions();
+        Ok(dungeon)
+    }
+
+    /// Side of the square dungeon in tiles.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// The open tile the player starts on.
+    pub fn spawn_point(&self) -> &Position {
+        &self.spawn_point
+    }
+
+    /// The open tile that takes the longest walk to reach from the spawn point.
+    pub fn farthest_from_spawn(&self) -> Position {
+        let start = (self.spawn_point.x() * self.size + self.spawn_point.y()) as usize;
+        let mut seen = vec![false; self.tiles.len()];
+        let mut queue = VecDeque::from(vec![start]);
+        seen[start] = true;
+        let mut last = start;
+        while let Some(i) = queue.pop_front() {
+            last = i;
+            let neighbours: Vec<usize> = self.neighbours(i).collect();
+            for neighbour in neighbours {
+                if !seen[neighbour] && self.tiles[neighbour].is_passable() {
+                    seen[neighbour] = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        Position::new(last as i32 / self.size, last as i32 % self.size)
+    }
+
+    /// Tile at `x`, `y`, or `None` outside the dungeon.
+    pub fn at(&self, x: i32, y: i32) -> Option<TileType> {
+        self.index(x, y).map(|i| self.tiles[i])
+    }
+
+    /// Writes the dungeon on `map` with its top left corner at `left`, `top`.
+    pub fn stamp(&self, map: &mut GameMap, left: i32, top: i32) {
+        for x in 0..self.size {
+            for y in 0..self.size {
+                map.replace_tile(left + x, top + y, Tile::new(self.tiles[(x * self.size + y) as usize], 0));
+            }
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.size && y < self.size {
+            Some((x * self.size + y) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, tile_type: TileType) {
+        // The outer ring always stays wall
+        if x > 0 && y > 0 && x < self.size - 1 && y < self.size - 1 {
+            self.tiles[(x * self.size + y) as usize] = tile_type;
+        }
+    }
+
+    fn is_open(&self, x: i32, y: i32) -> bool {
+        self.at(x, y).is_some_and(|tile_type| tile_type.is_passable())
+    }
+
+    // Splits the area in two until the parts are small enough, puts a room in each part and
+    // joins the two halves of every split with a corridor. Returns a point inside the rooms.
+    fn split_rooms(&mut self, left: i32, top: i32, width: i32, height: i32, rng: &mut GameRng) -> Position {
+        let can_split_x = width >= MIN_LEAF_SIZE * 2;
+        let can_split_y = height >= MIN_LEAF_SIZE * 2;
+        if !can_split_x && !can_split_y {
+            return self.carve_room(left, top, width, height, rng)
+        }
+        let split_x = if can_split_x && can_split_y { width > height || (width == height && rng.gen_bool(0.5)) } else { can_split_x };
+        let (first, second) = if split_x {
+            let split = rng.gen_range(MIN_LEAF_SIZE, width - MIN_LEAF_SIZE + 1);
+            (self.split_rooms(left, top, split, height, rng), self.split_rooms(left + split, top, width - split, height, rng))
+        } else {
+            let split = rng.gen_range(MIN_LEAF_SIZE, height - MIN_LEAF_SIZE + 1);
+            (self.split_rooms(left, top, width, split, rng), self.split_rooms(left, top + split, width, height - split, rng))
+        };
+        self.carve_corridor(&first, &second, rng);
+        if rng.gen_bool(0.5) { first } else { second }
+    }
+
+    fn carve_room(&mut self, left: i32, top: i32, width: i32, height: i32, rng: &mut GameRng) -> Position {
+        // Leave a wall between the room and the edge of its area
+        if width < 5 || height < 5 {
+            let center = Position::new(left + width / 2, top + height / 2);
+            self.set(center.x(), center.y(), TileType::Floor);
+            return center
+        }
+        let room_width = rng.gen_range(3, width - 1);
+        let room_height = rng.gen_range(3, height - 1);
+        let room_left = left + rng.gen_range(1, width - room_width);
+        let room_top = top + rng.gen_range(1, height - room_height);
+        for x in room_left..room_left + room_width {
+            for y in room_top..room_top + room_height {
+                self.set(x, y, TileType::Floor);
+            }
+        }
+        Position::new(room_left + room_width / 2, room_top + room_height / 2)
+    }
+
+    // L-shaped corridor, going either horizontally or vertically first.
+    fn carve_corridor(&mut self, from: &Position, to: &Position, rng: &mut GameRng) {
+        let corner = if rng.gen_bool(0.5) { Position::new(to.x(), from.y()) } else { Position::new(from.x(), to.y()) };
+        for (start, end) in [(from, &corner), (&corner, to)] {
+            let (dx, dy) = start.direction_for(end);
+            let mut position = *start;
+            while position != *end {
+                self.set(position.x(), position.y(), TileType::Floor);
+                position.move_relative(dx, dy);
+            }
+            self.set(end.x(), end.y(), TileType::Floor);
+        }
+    }
+
+    // Corridor tiles going straight through the wall of a room become doors, most of the time.
+    fn place_doors(&mut self, rng: &mut GameRng) {
+        for x in 1..self.size - 1 {
+            for y in 1..self.size - 1 {
+                if self.at(x, y) != Some(TileType::Floor) {
+                    continue
+                }
+                let walls = |a: (i32, i32), b: (i32, i32)| !self.is_open(a.0, a.1) && !self.is_open(b.0, b.1);
+                let open = |a: (i32, i32), b: (i32, i32)| self.is_open(a.0, a.1) && self.is_open(b.0, b.1);
+                let in_room = |a: (i32, i32)| self.count_open_around(a.0, a.1) >= 5;
+                let doorway = (walls((x - 1, y), (x + 1, y)) && open((x, y - 1), (x, y + 1)) && (in_room((x, y - 1)) || in_room((x, y + 1))))
+                    || (walls((x, y - 1), (x, y + 1)) && open((x - 1, y), (x + 1, y)) && (in_room((x - 1, y)) || in_room((x + 1, y))));
+                if doorway && rng.gen_bool(0.7) {
+                    self.set(x, y, TileType::Door);
+                }
+            }
+        }
+    }
+
+    fn count_open_around(&self, x: i32, y: i32) -> usize {
+        let mut count = 0;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if (dx, dy) != (0, 0) && self.is_open(x + dx, y + dy) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    // Random noise smoothed a few times: tiles surrounded by walls become walls and the others
+    // open up, leaving round caves.
+    fn grow_caves(&mut self, rng: &mut GameRng) {
+        for x in 1..self.size - 1 {
+            for y in 1..self.size - 1 {
+                if rng.gen_bool(0.55) {
+                    self.set(x, y, TileType::Floor);
+                }
+            }
+        }
+        for _ in 0..5 {
+            let mut next = self.tiles.clone();
+            for x in 1..self.size - 1 {
+                for y in 1..self.size - 1 {
+                    let walls = 8 - self.count_open_around(x, y);
+                    next[(x * self.size + y) as usize] = if walls >= 5 { TileType::StoneWall } else if walls <= 3 { TileType::Floor } else { self.tiles[(x * self.size + y) as usize] };
+                }
+            }
+            self.tiles = next;
+        }
+    }
+
+    // Wanders from the center, digging every tile it steps on, until a third of the map is open.
+    fn dig_tunnels(&mut self, rng: &mut GameRng) {
+        let goal = ((self.size - 2) * (self.size - 2) / 3) as usize;
+        let mut position = Position::new(self.size / 2, self.size / 2);
+        let mut dug = 0;
+        while dug < goal {
+            if !self.is_open(position.x(), position.y()) {
+                self.set(position.x(), position.y(), TileType::Floor);
+                dug += 1;
+            }
+            let (dx, dy) = ORTHOGONAL[rng.gen_range(0, 4)];
+            if position.x() + dx > 0 && position.y() + dy > 0 && position.x() + dx < self.size - 1 && position.y() + dy < self.size - 1 {
+                position.move_relative(dx, dy);
+            }
+        }
+    }
+
+    // Picks the spawn point in the biggest open region, then digs the shortest way from every
+    // other region to the ones already connected.
+    fn connect_regions(&mut self) {
+        let mut regions = self.regions();
+        if regions.is_empty() {
+            let center = self.size / 2;
+            self.set(center, center, TileType::Floor);
+            self.spawn_point = Position::new(center, center);
+            return
+        }
+        regions.sort_by_key(|region| std::cmp::Reverse(region.len()));
+        let center = Position::new(self.size / 2, self.size / 2);
+        let main = &regions[0];
+        self.spawn_point = main.iter().copied().filter(|&i| self.tiles[i] == TileType::Floor)
+            .map(|i| Position::new(i as i32 / self.size, i as i32 % self.size))
+            .min_by(|a, b| a.distance_from(&center).total_cmp(&b.distance_from(&center)))
+            .unwrap_or_else(|| Position::new(main[0] as i32 / self.size, main[0] as i32 % self.size));
+        let mut connected = vec![false; self.tiles.len()];
+        for &i in main {
+            connected[i] = true;
+        }
+        for region in &regions[1..] {
+            if region.iter().any(|&i| connected[i]) {
+                continue
+            }
+            self.dig_to_connected(region, &mut connected);
+        }
+    }
+
+    fn regions(&self) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.tiles.len()];
+        let mut regions = Vec::new();
+        for start in 0..self.tiles.len() {
+            if seen[start] || !self.tiles[start].is_passable() {
+                continue
+            }
+            let mut region = vec![start];
+            seen[start] = true;
+            let mut next = 0;
+            while next < region.len() {
+                let i = region[next];
+                next += 1;
+                for neighbour in self.neighbours(i) {
+                    if !seen[neighbour] && self.tiles[neighbour].is_passable() {
+                        seen[neighbour] = true;
+                        region.push(neighbour);
+                    }
+                }
+            }
+            regions.push(region);
+        }
+        regions
+    }
+
+    // Orthogonal neighbours only, so every region can be walked without cutting corners.
+    fn neighbours(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = (i as i32 / self.size, i as i32 % self.size);
+        ORTHOGONAL.iter().filter_map(move |(dx, dy)| self.index(x + dx, y + dy))
+    }
+
+    // Breadth-first search through walls from the whole region at once, stopping at the first
+    // connected tile, and digs the path it found.
+    fn dig_to_connected(&mut self, region: &[usize], connected: &mut [bool]) {
+        let mut parents = vec![usize::MAX; self.tiles.len()];
+        let mut queue: VecDeque<usize> = region.iter().copied().collect();
+        for &i in region {
+            parents[i] = i;
+        }
+        while let Some(i) = queue.pop_front() {
+            if connected[i] {
+                let mut step = i;
+                while parents[step] != step {
+                    step = parents[step];
+                    connected[step] = true;
+                    if !self.tiles[step].is_passable() {
+                        self.tiles[step] = TileType::Floor;
+                    }
+                }
+                break
+            }
+            let neighbours: Vec<usize> = self.neighbours(i).collect();
+            for neighbour in neighbours {
+                let (x, y) = (neighbour as i32 / self.size, neighbour as i32 % self.size);
+                let on_edge = x == 0 || y == 0 || x == self.size - 1 || y == self.size - 1;
+                if parents[neighbour] == usize::MAX && !on_edge {
+                    parents[neighbour] = i;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        for &i in region {
+            connected[i] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    const GENERATORS: [MapType; 3] = [MapType::Rooms, MapType::Caves, MapType::Tunnels];
+
+    // Passable tiles the spawn point can't walk to
+    fn unreachable_tiles(dungeon: &Dungeon) -> usize {
+        let start = dungeon.index(dungeon.spawn_point.x(), dungeon.spawn_point.y()).unwrap();
+        let mut seen = vec![false; dungeon.tiles.len()];
+        let mut queue = VecDeque::from(vec![start]);
+        seen[start] = true;
+        while let Some(i) = queue.pop_front() {
+            for neighbour in dungeon.neighbours(i).collect::<Vec<_>>() {
+                if !seen[neighbour] && dungeon.tiles[neighbour].is_passable() {
+                    seen[neighbour] = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        dungeon.tiles.iter().zip(&seen).filter(|(tile, seen)| tile.is_passable() && !**seen).count()
+    }
+
+    #[test]
+    fn every_open_tile_can_be_reached_from_the_spawn_point() {
+        for map_type in &GENERATORS {
+            for seed in 0..20 {
+                let dungeon = Dungeon::generate(*map_type, 40, &mut GameRng::seed_from_u64(seed)).unwrap();
+                assert!(dungeon.at(dungeon.spawn_point.x(), dungeon.spawn_point.y()).unwrap().is_passable());
+                assert_eq!(unreachable_tiles(&dungeon), 0, "{} dungeon with seed {}", map_type, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn the_smallest_dungeons_are_still_connected() {
+        for map_type in &GENERATORS {
+            for seed in 0..20 {
+                let dungeon = Dungeon::generate(*map_type, MIN_DUNGEON_SIZE, &mut GameRng::seed_from_u64(seed)).unwrap();
+                assert_eq!(dungeon.size(), MIN_DUNGEON_SIZE);
+                assert_eq!(unreachable_tiles(&dungeon), 0, "{} dungeon with seed {}", map_type, seed);
+            }
+            assert!(Dungeon::generate(*map_type, MIN_DUNGEON_SIZE - 1, &mut GameRng::seed_from_u64(0)).is_err());
+            assert!(Dungeon::generate(*map_type, 0, &mut GameRng::seed_from_u64(0)).is_err());
+        }
+    }
+
+    #[test]
+    fn the_overworld_is_not_a_dungeon() {
+        assert!(Dungeon::generate(MapType::Overworld, DEFAULT_DUNGEON_SIZE, &mut GameRng::seed_from_u64(0)).is_err());
+    }
+}
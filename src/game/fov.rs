@@ -0,0 +1,225 @@
+use super::map::GameMap;
+use super::position::Position;
+
+// Multipliers turning the coordinates of the first octant into each of the eight
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1)
+];
+
+/// Tiles visible from a position within a radius, with opaque tiles casting shadows behind them.
+#[derive(Clone, Default)]
+pub struct FieldOfView {
+    origin: Position,
+    radius: i32,
+    // Square of side 2 * radius + 1 centered on the origin
+    visible: Vec<bool>
+}
+
+impl FieldOfView {
+    /// Runs recursive shadowcasting from `origin`. Tiles that aren't loaded block the view.
+    pub fn compute(map: &GameMap, origin: &Position, radius: i32) -> FieldOfView {
+        let side = (2 * radius + 1).max(0) as usize;
+        let mut fov = FieldOfView {
+            origin: *origin,
+            radius,
+            visible: vec![false; side * side]
+        };
+        if radius <= 0 {
+            return fov
+        }
+        // Copy which tiles are opaque first, going through the map a chunk at a time is much
+        // cheaper than looking up every tile the light reaches
+        let mut opaque = vec![true; side * side];
+        for (position, tile) in map.view_around(origin, radius).iter() {
+            if let Some(i) = fov.index(position.x(), position.y()) {
+                opaque[i] = tile.tile_type().is_opaque();
+            }
+        }
+        fov.set_visible(origin.x(), origin.y());
+        for octant in &OCTANTS {
+            fov.cast_light(&opaque, 1, 1.0, 0.0, *octant);
+        }
+        fov
+    }
+
+    /// Where the field of view was seen from.
+    pub fn origin(&self) -> &Position {
+        &self.origin
+    }
+
+    /// How far it reaches from the origin.
+    pub fn radius(&self) -> i32 {
+        self.radius
+    }
+
+    /// Whether `position` can be seen, always false out of range.
+    pub fn is_visible(&self, position: &Position) -> bool {
+        match self.index(position.x(), position.y()) {
+            Some(i) => self.visible[i],
+            None => false
+        }
+    }
+
+    /// Every visible position.
+    pub fn iter(&self) -> impl Iterator<Item = Position> + '_ {
+        let side = 2 * self.radius + 1;
+        self.visible.iter().enumerate().filter(|(_, visible)| **visible).map(move |(i, _)| {
+            let i = i as i32;
+            Position::new(self.origin.x() - self.radius + i / side, self.origin.y() - self.radius + i % side)
+        })
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let (dx, dy) = (x - self.origin.x() + self.radius, y - self.origin.y() + self.radius);
+        let side = 2 * self.radius + 1;
+        if self.radius > 0 && dx >= 0 && dy >= 0 && dx < side && dy < side {
+            Some((dx * side + dy) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn set_visible(&mut self, x: i32, y: i32) {
+        if let Some(i) = self.index(x, y) {
+            self.visible[i] = true;
+        }
+    }
+
+    // Scans the rows of one octant going away from the origin, between the `start` and `end`
+    // slopes. Every opaque tile narrows the light and the part it hides is skipped by recursing
+    // on what is left beside it.
+    fn cast_light(&mut self, opaque_tiles: &[bool], row: i32, mut start: f32, end: f32, (xx, xy, yx, yy): (i32, i32, i32, i32)) {
+        if start < end {
+            return
+        }
+        let radius_squared = self.radius * self.radius;
+        let mut new_start = 0.0;
+        for distance in row..=self.radius {
+            let dy = -distance;
+            let mut blocked = false;
+            for dx in -distance..=0 {
+                let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right_slope {
+                    continue
+                } else if end > left_slope {
+                    break
+                }
+                let x = self.origin.x() + dx * xx + dy * xy;
+                let y = self.origin.y() + dx * yx + dy * yy;
+                if dx * dx + dy * dy < radius_squared {
+                    self.set_visible(x, y);
+                }
+                let opaque = self.index(x, y).is_none_or(|i| opaque_tiles[i]);
+                if blocked {
+                    if opaque {
+                        new_start = right_slope;
+                    } else {
+                        blocked = false;
+                        start = new_start;
+                    }
+                } else if opaque && distance < self.radius {
+                    blocked = true;
+                    self.cast_light(opaque_tiles, distance + 1, start, left_slope, (xx, xy, yx, yy));
+                    new_start = right_slope;
+                }
+            }
+            if blocked {
+                break
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+    use super::super::map::{Tile, TileType};
+
+    const SIZE: i32 = 21;
+    const CENTER: (i32, i32) = (SIZE / 2, SIZE / 2);
+
+    // A floor the size of the view with walls at `walls`, relative to the center
+    fn map(walls: &[(i32, i32)]) -> GameMap {
+        let mut map = GameMap::new(0, Some(SIZE as usize));
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                let wall = walls.contains(&(x - CENTER.0, y - CENTER.1));
+                map.replace_tile(x, y, Tile::new(if wall { TileType::StoneWall } else { TileType::Floor }, 0));
+            }
+        }
+        map
+    }
+
+    // Visible tiles relative to the center
+    fn visible(map: &GameMap, radius: i32) -> HashSet<(i32, i32)> {
+        let fov = FieldOfView::compute(map, &Position::new(CENTER.0, CENTER.1), radius);
+        fov.iter().map(|position| (position.x() - CENTER.0, position.y() - CENTER.1)).collect()
+    }
+
+    fn is_visible(map: &GameMap, radius: i32, (dx, dy): (i32, i32)) -> bool {
+        let fov = FieldOfView::compute(map, &Position::new(CENTER.0, CENTER.1), radius);
+        fov.is_visible(&Position::new(CENTER.0 + dx, CENTER.1 + dy))
+    }
+
+    #[test]
+    fn origin_is_always_visible() {
+        let surrounded: Vec<(i32, i32)> = (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy)))
+            .filter(|&offset| offset != (0, 0))
+            .collect();
+        let map = map(&surrounded);
+        assert_eq!(visible(&map, 5), surrounded.iter().copied().chain(Some((0, 0))).collect());
+        assert_eq!(visible(&map, 1), [(0, 0)].iter().copied().collect());
+        // Nothing at all without a radius
+        assert!(visible(&map, 0).is_empty());
+    }
+
+    #[test]
+    fn open_ground_is_visible_within_the_radius() {
+        let map = map(&[]);
+        for radius in 1..=8 {
+            let expected: HashSet<(i32, i32)> = (-radius..=radius)
+                .flat_map(|dx| (-radius..=radius).map(move |dy| (dx, dy)))
+                .filter(|(dx, dy)| (dx * dx + dy * dy < radius * radius) || (*dx, *dy) == (0, 0))
+                .collect();
+            assert_eq!(visible(&map, radius), expected, "radius {}", radius);
+        }
+    }
+
+    #[test]
+    fn walls_are_visible_and_hide_what_is_behind() {
+        let walls = [(3, 0), (-3, 0), (0, 3), (0, -3), (2, 2), (-2, -2)];
+        let map = map(&walls);
+        for &(x, y) in walls.iter() {
+            let (step_x, step_y) = (x.signum(), y.signum());
+            assert!(is_visible(&map, 8, (x, y)), "wall at {}, {}", x, y);
+            assert!(is_visible(&map, 8, (x - step_x, y - step_y)), "in front of {}, {}", x, y);
+            for behind in 1..=3 {
+                let tile = (x + step_x * behind, y + step_y * behind);
+                assert!(!is_visible(&map, 8, tile), "{}, {} behind {}, {}", tile.0, tile.1, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn every_octant_casts_the_same_shadows() {
+        // An uneven pattern in the first octant, so any mixed up axis shows
+        let pattern = [(1, -3), (0, -5), (3, -4), (2, -7)];
+        let reference = visible(&map(&pattern), 9);
+        // Mirror along each axis and swap them, turning the first octant into each of the others
+        for &(xx, xy, yx, yy) in OCTANTS.iter() {
+            let transform = |(x, y): (i32, i32)| (x * xx + y * xy, x * yx + y * yy);
+            let walls: Vec<(i32, i32)> = pattern.iter().copied().map(transform).collect();
+            let expected: HashSet<(i32, i32)> = reference.iter().copied().map(transform).collect();
+            assert_eq!(visible(&map(&walls), 9), expected, "octant {:?}", (xx, xy, yx, yy));
+        }
+    }
+}
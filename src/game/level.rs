@@ -0,0 +1,89 @@
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+use super::GameRng;
+use super::animals;
+use super::config::GameConfig;
+use super::dungeon::{Dungeon, MapType, DEFAULT_DUNGEON_SIZE};
+use super::map::{GameMap, Tile, TileType};
+use super::position::Position;
+use super::registry::EntityId;
+use super::world::World;
+
+// Generators the levels below the surface are picked from
+const UNDERGROUND_TYPES: [MapType; 3] = [MapType::Rooms, MapType::Caves, MapType::Tunnels];
+
+/// Where the stairs of a level are, if it has them.
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+pub struct Stairs {
+    /// Leads back to the level above.
+    pub up: Option<Position>,
+    /// Leads to the next level below.
+    pub down: Option<Position>
+}
+
+/// A level the player isn't on, kept as it was left.
+pub struct Level {
+    /// Tiles of the level, with what the player explored.
+    pub map: GameMap,
+    /// Entities left on the level, with a slot kept for the player.
+    pub world: World,
+    /// Where the stairs of the level are.
+    pub stairs: Stairs
+}
+
+impl Level {
+    /// Generates the dungeon at `depth`, 1 being right below the surface. Every level has its
+    /// own seed, derived from the config's, so it comes out the same whenever it's first visited.
+    /// The slot of `player` is kept free for it to arrive with the same ID.
+    pub fn generate(config: &GameConfig, depth: usize, player: EntityId) -> Level {
+        let mut rng = GameRng::seed_from_u64(config.seed ^ (depth as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let map_type = UNDERGROUND_TYPES[rng.gen_range(0, UNDERGROUND_TYPES.len())];
+        let mut map = GameMap::new(rng.gen(), Some(DEFAULT_DUNGEON_SIZE));
+        let dungeon = Dungeon::generate(map_type, DEFAULT_DUNGEON_SIZE, &mut rng).expect("underground levels are dungeons");
+        dungeon.stamp(&mut map, 0, 0);
+        let up = *dungeon.spawn_point();
+        let down = dungeon.farthest_from_spawn();
+        map.replace_tile(up.x(), up.y(), Tile::new(TileType::StairsUp, 0));
+        map.replace_tile(down.x(), down.y(), Tile::new(TileType::StairsDown, 0));
+        // The player keeps its ID on every level, so its slot is never given away
+        let mut world = World::new();
+        world.reserve(player);
+        spawn_animals(&mut world, &mut map, &up, config.animals, config.spawn_radius, &mut rng);
+        Level { map, world, stairs: Stairs { up: Some(up), down: Some(down) } }
+    }
+}
+
+/// Places up to `count` animals on open tiles within `radius` of `center`, leaving `center` itself
+/// free for the player. Only the chunks of the spots tried are generated, so a wide radius
+/// costs no more than a narrow one.
+pub fn spawn_animals(world: &mut World, map: &mut GameMap, center: &Position, count: usize, radius: i32, rng: &mut GameRng) {
+    let (mut spawned, mut attempts) = (0, 0);
+    while spawned < count && attempts < count * 100 {
+        attempts += 1;
+        let position = Position::new(center.x() + rng.gen_range(-radius, radius), center.y() + rng.gen_range(-radius, radius));
+        let is_free = position != *center && !world.is_blocked(&position);
+        if is_free {
+            map.load_around(&position, 0);
+        }
+        if is_free && map.at(position.x(), position.y()).is_some_and(Tile::is_passable) {
+            world.spawn(animals::animal(position));
+            spawned += 1;
+        }
+    }
+}
+
+/// The closest loaded open tile at least `min_distance` tiles away from `center` on some axis.
+pub fn open_tile_near(map: &GameMap, center: &Position, min_distance: i32, max_distance: i32) -> Option<Position> {
+    for distance in min_distance..=max_distance {
+        for offset in -distance..=distance {
+            let ring = [(offset, -distance), (offset, distance), (-distance, offset), (distance, offset)];
+            for (dx, dy) in ring.iter() {
+                let position = Position::new(center.x() + dx, center.y() + dy);
+                if map.at(position.x(), position.y()).is_some_and(Tile::is_passable) {
+                    return Some(position)
+                }
+            }
+        }
+    }
+    None
+}
@@ -1,117 +1,677 @@
-use std::cmp::{min,max};
-use super::RNG;
-use rand::Rng;
+use std::cmp::{min, max};
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::fmt::Write;
+use super::GameRng;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
 use super::Position;
+use super::prefab::{Orientation, Prefab};
+use super::terrain::{Biome, Terrain};
 
-const MAP_SIZE: usize = 1000;
-
-#[derive(Copy, Clone)]
+/// What a tile is made of, deciding whether it can be walked on and seen through.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TileType {
+    /// Bare ground.
     Dirt,
-    Grass
+    /// Most of the overworld.
+    Grass,
+    /// Walls of dungeons and buildings.
+    StoneWall,
+    /// Water too deep to wade through.
+    DeepWater,
+    /// Water shallow enough to wade through.
+    ShallowWater,
+    /// Blocks both the way and the view.
+    Tree,
+    /// Blocks the way but not the view.
+    Boulder,
+    /// Ground of deserts.
+    Sand,
+    /// Ground of swamps.
+    Mud,
+    /// Mountain peaks.
+    Rock,
+    /// Floors of dungeons and buildings.
+    Floor,
+    /// Can be walked through but not seen through.
+    Door,
+    /// Leads to the next level below.
+    StairsDown,
+    /// Leads back to the level above.
+    StairsUp
 }
 
-#[derive(Copy, Clone)]
-pub struct Tile {
-    pub tile_type: TileType,
-    pub variant: usize,
-    position: Position,
-    passable: bool
+impl TileType {
+    /// How many tile types there are, numbered from 0 by [`TileType::index`].
+    pub const COUNT: u8 = 14;
+
+    /// The type numbered `index`, if there is one.
+    pub const fn from_index(index: u8) -> Option<TileType> {
+        match index {
+            0 => Some(TileType::Dirt),
+            1 => Some(TileType::Grass),
+            2 => Some(TileType::StoneWall),
+            3 => Some(TileType::DeepWater),
+            4 => Some(TileType::ShallowWater),
+            5 => Some(TileType::Tree),
+            6 => Some(TileType::Boulder),
+            7 => Some(TileType::Sand),
+            8 => Some(TileType::Mud),
+            9 => Some(TileType::Rock),
+            10 => Some(TileType::Floor),
+            11 => Some(TileType::Door),
+            12 => Some(TileType::StairsDown),
+            13 => Some(TileType::StairsUp),
+            _ => None
+        }
+    }
+
+    /// Number of the type, as stored in save files.
+    pub fn index(&self) -> u8 {
+        match self {
+            TileType::Dirt => 0,
+            TileType::Grass => 1,
+            TileType::StoneWall => 2,
+            TileType::DeepWater => 3,
+            TileType::ShallowWater => 4,
+            TileType::Tree => 5,
+            TileType::Boulder => 6,
+            TileType::Sand => 7,
+            TileType::Mud => 8,
+            TileType::Rock => 9,
+            TileType::Floor => 10,
+            TileType::Door => 11,
+            TileType::StairsDown => 12,
+            TileType::StairsUp => 13
+        }
+    }
+
+    /// Whether entities can walk on the tile.
+    pub fn is_passable(&self) -> bool {
+        match self {
+            TileType::Dirt | TileType::Grass | TileType::ShallowWater | TileType::Sand | TileType::Mud | TileType::Floor | TileType::Door
+                | TileType::StairsDown | TileType::StairsUp => true,
+            TileType::StoneWall | TileType::DeepWater | TileType::Tree | TileType::Boulder | TileType::Rock => false
+        }
+    }
+
+    /// Whether the tile blocks the view of what is behind it.
+    pub fn is_opaque(&self) -> bool {
+        matches!(self, TileType::StoneWall | TileType::Tree | TileType::Rock | TileType::Door)
+    }
+
+    /// Name shown when inspecting the tile.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TileType::Dirt => "Dirt",
+            TileType::Grass => "Grass",
+            TileType::StoneWall => "Stone Wall",
+            TileType::DeepWater => "Deep Water",
+            TileType::ShallowWater => "Shallow Water",
+            TileType::Tree => "Tree",
+            TileType::Boulder => "Boulder",
+            TileType::Sand => "Sand",
+            TileType::Mud => "Mud",
+            TileType::Rock => "Rock",
+            TileType::Floor => "Floor",
+            TileType::Door => "Door",
+            TileType::StairsDown => "Stairs Down",
+            TileType::StairsUp => "Stairs Up"
+        }
+    }
 }
 
+// Tiles keep their type in 4 bits, so a new one must fit in there and be counted
+const _: () = assert!(TileType::COUNT <= 16, "tile types don't fit in 4 bits anymore");
+const _: () = assert!(TileType::from_index(TileType::COUNT - 1).is_some() && TileType::from_index(TileType::COUNT).is_none(),
+    "TileType::COUNT doesn't match the tile types");
+
+/// A single cell of the map, packed in one byte: the type in the high bits and the variant in
+/// the low ones. Its position is given by where it is stored. There is no room for flags and
+/// no need for them: whether a tile was explored is kept by the map apart from its chunk, so it
+/// outlives the chunk being dropped, and whether it is visible by the field of view of whoever
+/// looks at it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Tile(u8);
+
 impl Tile {
-    pub fn new(x: usize, y: usize) -> Tile {
-        Tile {
-            tile_type: TileType::Dirt,
-            variant: 0,
-            position: Position::new(x as i32, y as i32),
-            passable: true
-        }
+    /// A tile of `tile_type`, keeping the low 4 bits of `variant`.
+    pub fn new(tile_type: TileType, variant: u8) -> Tile {
+        Tile(tile_type.index() << 4 | (variant & 0x0f))
     }
 
-    pub fn position(&self) -> &Position {
-        &self.position 
+    /// What the tile is made of.
+    pub fn tile_type(&self) -> TileType {
+        // Only ever built from a valid type by `new` and `unpack`
+        TileType::from_index(self.0 >> 4).unwrap_or(TileType::Dirt)
     }
 
+    /// Which look of its type the tile has, from 0 to 15.
+    pub fn variant(&self) -> u8 {
+        self.0 & 0x0f
+    }
+
+    /// Whether entities can walk on the tile.
     pub fn is_passable(&self) -> bool {
-        self.passable
+        self.tile_type().is_passable()
     }
 
+    /// Name shown when inspecting the tile.
     pub fn name(&self) -> &str {
-        match self.tile_type {
-            TileType::Grass => "Grass",
-            TileType::Dirt => "Dirt"
+        self.tile_type().name()
+    }
+
+    /// Type and variant in one byte, as stored in save files.
+    pub fn pack(&self) -> u8 {
+        self.0
+    }
+
+    /// Reads back a byte from [`Tile::pack`], or `None` if it has no valid type.
+    pub fn unpack(packed: u8) -> Option<Tile> {
+        Some(Tile::new(TileType::from_index(packed >> 4)?, packed & 0x0f))
+    }
+}
+
+impl Default for Tile {
+    fn default() -> Self {
+        Tile::new(TileType::Dirt, 0)
+    }
+}
+
+/// Width and height in tiles of the blocks the map is generated and stored in.
+pub const CHUNK_SIZE: i32 = 32;
+
+// About one ruin every 40000 tiles
+const RUIN_CHANCE: f64 = 0.025;
+
+// Chance of a chunk trying to fit a prefab, and how many spots it tries
+const PREFAB_CHANCE: f64 = 0.1;
+const PREFAB_ATTEMPTS: usize = 10;
+
+/// Coordinates of the chunk containing the tile at `x`, `y`.
+pub fn chunk_coords(x: i32, y: i32) -> (i32, i32) {
+    (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE))
+}
+
+// Every tile lookup goes through the chunk table, and chunk coordinates don't need the
+// protection of the default hasher against crafted keys, so a multiply per coordinate will do.
+#[derive(Default)]
+pub(crate) struct ChunkHasher(u64);
+
+impl Hasher for ChunkHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u32(*byte as u32);
+        }
+    }
+
+    fn write_i32(&mut self, n: i32) {
+        self.write_u32(n as u32);
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (self.0.rotate_left(5) ^ n as u64).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+struct Chunk {
+    tiles: Vec<Tile>,
+    // Changed since it was generated, so it can't be dropped and regenerated from the seed
+    modified: bool
+}
+
+impl Chunk {
+    fn index(x: i32, y: i32) -> usize {
+        (x.rem_euclid(CHUNK_SIZE) * CHUNK_SIZE + y.rem_euclid(CHUNK_SIZE)) as usize
+    }
+
+    fn iter(&self, (chunk_x, chunk_y): (i32, i32)) -> impl Iterator<Item = (Position, &Tile)> {
+        self.tiles.iter().enumerate().map(move |(i, tile)| {
+            let i = i as i32;
+            (Position::new(chunk_x * CHUNK_SIZE + i / CHUNK_SIZE, chunk_y * CHUNK_SIZE + i % CHUNK_SIZE), tile)
+        })
+    }
+
+    // Every chunk gets its own RNG so it comes out the same no matter when it's generated.
+    // Returns where the animals of its prefab should spawn.
+    fn generate(terrain: &Terrain, seed: u64, (chunk_x, chunk_y): (i32, i32)) -> (Chunk, Vec<Position>) {
+        let mut rng = GameRng::seed_from_u64(seed ^ ((chunk_x as u32 as u64) << 32 | chunk_y as u32 as u64));
+        let (left, top) = (chunk_x * CHUNK_SIZE, chunk_y * CHUNK_SIZE);
+        let mut tiles = Vec::with_capacity((CHUNK_SIZE * CHUNK_SIZE) as usize);
+        for x in left..left + CHUNK_SIZE {
+            for y in top..top + CHUNK_SIZE {
+                tiles.push(terrain.tile_at(x, y, &mut rng));
+            }
+        }
+        let mut chunk = Chunk { tiles, modified: false };
+        if rng.gen_bool(RUIN_CHANCE) {
+            chunk.generate_ruin(terrain, left, top, &mut rng);
+        }
+        let mut spawns = Vec::new();
+        if rng.gen_bool(PREFAB_CHANCE) {
+            spawns = chunk.generate_prefab(left, top, &mut rng);
+            // Its animals are only spawned once, so it can't be dropped and generated again
+            chunk.modified = !spawns.is_empty();
+        }
+        (chunk, spawns)
+    }
+
+    fn set_tile_type(&mut self, x: i32, y: i32, tile_type: TileType) {
+        self.tiles[Chunk::index(x, y)] = Tile::new(tile_type, 0);
+    }
+
+    // Crumbling rectangle of stone walls with a dirt floor, built on dry and flat land.
+    fn generate_ruin(&mut self, terrain: &Terrain, chunk_left: i32, chunk_top: i32, rng: &mut GameRng) {
+        let width = rng.gen_range(4, 12);
+        let height = rng.gen_range(4, 12);
+        let left = chunk_left + rng.gen_range(0, CHUNK_SIZE - width);
+        let top = chunk_top + rng.gen_range(0, CHUNK_SIZE - height);
+        match terrain.biome_at(left + width / 2, top + height / 2) {
+            Biome::Lake | Biome::Swamp | Biome::Mountains => return,
+            _ => {}
+        }
+        for x in left..left + width {
+            for y in top..top + height {
+                let is_edge = x == left || y == top || x == left + width - 1 || y == top + height - 1;
+                if is_edge && rng.gen_bool(0.8) {
+                    self.set_tile_type(x, y, TileType::StoneWall);
+                } else {
+                    self.set_tile_type(x, y, TileType::Dirt);
+                }
+            }
         }
     }
+
+    // A built-in prefab, randomly turned, wherever it fits whole in the chunk.
+    fn generate_prefab(&mut self, chunk_left: i32, chunk_top: i32, rng: &mut GameRng) -> Vec<Position> {
+        let prefabs = Prefab::builtin();
+        let prefab = &prefabs[rng.gen_range(0, prefabs.len())];
+        for _ in 0..PREFAB_ATTEMPTS {
+            let orientation = Orientation::random(rng);
+            let (width, height) = prefab.size(orientation);
+            let left = chunk_left + rng.gen_range(0, CHUNK_SIZE - width);
+            let top = chunk_top + rng.gen_range(0, CHUNK_SIZE - height);
+            let tiles = &self.tiles;
+            if prefab.fits(|x, y| Some(tiles[Chunk::index(x, y)].tile_type()), left, top, orientation) {
+                return prefab.stamp(|x, y, tile_type| self.set_tile_type(x, y, tile_type), left, top, orientation)
+            }
+        }
+        Vec::new()
+    }
 }
 
+// One bit per tile of a chunk, in the same order as its tiles
+type ExploredTiles = [u64; (CHUNK_SIZE * CHUNK_SIZE / 64) as usize];
+
+/// The world's tiles, generated chunk by chunk from a seed as they are needed. Without a `size`
+/// the map goes on forever; with one it is a square `size` tiles wide and tall.
 pub struct GameMap {
-    tiles: Vec<Tile>
+    seed: u64,
+    size: Option<usize>,
+    terrain: Terrain,
+    chunks: HashMap<(i32, i32), Chunk, BuildHasherDefault<ChunkHasher>>,
+    // Kept apart from the chunks so these can still be dropped once explored
+    explored: HashMap<(i32, i32), ExploredTiles, BuildHasherDefault<ChunkHasher>>,
+    // Animals of the prefabs generated since they were last taken
+    spawns: Vec<Position>
 }
 
 impl GameMap {
-    pub fn new() -> GameMap {
-        GameMap { tiles: Vec::new() }
+    /// An empty map generating its chunks from `seed`, bounded if it has a `size`.
+    pub fn new(seed: u64, size: Option<usize>) -> GameMap {
+        GameMap {
+            seed,
+            size,
+            terrain: Terrain::new(&mut GameRng::seed_from_u64(seed)),
+            chunks: HashMap::default(),
+            explored: HashMap::default(),
+            spawns: Vec::new()
+        }
     }
 
-    pub fn center() -> usize {
-        MAP_SIZE / 2 
+    /// What the chunks are generated from.
+    pub fn seed(&self) -> u64 {
+        self.seed
     }
 
+    /// Side of a bounded map in tiles, `None` if it goes on forever.
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Where the overworld's biomes are.
+    pub fn terrain(&self) -> &Terrain {
+        &self.terrain
+    }
+
+    /// Middle of a bounded map, the origin of an unbounded one.
+    pub fn center(&self) -> Position {
+        match self.size {
+            Some(size) => Position::new((size / 2) as i32, (size / 2) as i32),
+            None => Position::new(0, 0)
+        }
+    }
+
+    /// Whether `x`, `y` is on the map, always true for an unbounded one.
     pub fn is_in_bounds(&self, x: i32, y: i32) -> bool {
-        x >= 0 && y >= 0 && (x as usize) < MAP_SIZE && (y as usize) < MAP_SIZE
+        match self.size {
+            Some(size) => x >= 0 && y >= 0 && (x as usize) < size && (y as usize) < size,
+            None => true
+        }
     }
 
+    /// The tile at `x`, `y`, or `None` if it is off the map or its chunk isn't loaded.
     pub fn at(&self, x: i32, y: i32) -> Option<&Tile> {
-        if self.is_in_bounds(x, y) {
-            Some(&self.tiles[MAP_SIZE * (x as usize) + (y as usize)])
-        } else {
-            None
+        if !self.is_in_bounds(x, y) {
+            return None
         }
+        self.chunks.get(&chunk_coords(x, y)).map(|chunk| &chunk.tiles[Chunk::index(x, y)])
     }
 
+    /// Changes a tile, generating its chunk first if needed. The chunk is kept from then on.
     pub fn replace_tile(&mut self, x: i32, y: i32, tile: Tile) {
         if self.is_in_bounds(x, y) {
-            self.tiles[MAP_SIZE * (x as usize) + (y as usize)] = tile;
+            let chunk = self.load_chunk(chunk_coords(x, y));
+            chunk.tiles[Chunk::index(x, y)] = tile;
+            chunk.modified = true;
         }
     }
 
-    pub fn index_to_position(&self, i: usize) -> (usize, usize) {
-        (i / MAP_SIZE, i % MAP_SIZE)
+    /// Whether the player has ever seen the tile at `x`, `y`.
+    pub fn is_explored(&self, x: i32, y: i32) -> bool {
+        let i = Chunk::index(x, y);
+        self.explored.get(&chunk_coords(x, y)).is_some_and(|bits| bits[i / 64] & 1 << (i % 64) != 0)
+    }
+
+    /// Remembers that the player saw the tile at `x`, `y`.
+    pub fn explore(&mut self, x: i32, y: i32) {
+        if self.is_in_bounds(x, y) {
+            let i = Chunk::index(x, y);
+            self.explored.entry(chunk_coords(x, y)).or_default()[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    /// How many chunks are generated and kept in memory.
+    pub fn loaded_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Every loaded tile with its position, a chunk at a time in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Position, &Tile)> {
+        self.chunks.iter().flat_map(move |(&coords, chunk)| chunk.iter(coords)).filter(move |(position, _)| self.is_in_bounds(position.x(), position.y()))
+    }
+
+    /// Borrows the square of tiles within `distance` of `pos` on both axes.
+    pub fn view_around(&self, pos: &Position, distance: i32) -> MapView<'_> {
+        MapView {
+            map: self,
+            left: pos.x() - distance,
+            top: pos.y() - distance,
+            right: pos.x() + distance + 1,
+            bottom: pos.y() + distance + 1
+        }
     }
 
-    pub fn portion_around(&self, pos: &Position, distance: usize) -> Vec<Tile> {
-        let mut portion: Vec<Tile> = Vec::new();
-        let range_x_min = max(0, pos.x() - distance as i32) as usize;
-        let range_x_max = min(MAP_SIZE as i32, pos.x() + distance as i32) as usize;
-        let range_y_min = max(0, pos.y() - distance as i32) as usize;
-        let range_y_max = min(MAP_SIZE as i32, pos.y() + distance as i32) as usize;
-        for x in range_x_min..range_x_max {
-            for y in range_y_min..range_y_max {
-                match self.at(x as i32, y as i32) {
-                    Some(tile) => {
-                        portion.push(tile.clone());
-                    },
-                    _ => ()
+    /// Generates the missing chunks within `radius` tiles of `pos` on both axes.
+    pub fn load_around(&mut self, pos: &Position, radius: i32) {
+        let (min_x, min_y) = chunk_coords(pos.x() - radius, pos.y() - radius);
+        let (max_x, max_y) = chunk_coords(pos.x() + radius, pos.y() + radius);
+        for chunk_x in min_x..=max_x {
+            for chunk_y in min_y..=max_y {
+                if self.is_chunk_in_bounds(chunk_x, chunk_y) {
+                    self.load_chunk((chunk_x, chunk_y));
                 }
-            } 
+            }
+        }
+    }
+
+    /// Drops the unmodified chunks that aren't within the radius of any of `anchors`. They will
+    /// be generated again, identical, when something comes back near them.
+    pub fn unload_far_from(&mut self, anchors: &[(Position, i32)]) {
+        self.chunks.retain(|&(chunk_x, chunk_y), chunk| {
+            chunk.modified || anchors.iter().any(|(pos, radius)| {
+                let (min_x, min_y) = chunk_coords(pos.x() - radius, pos.y() - radius);
+                let (max_x, max_y) = chunk_coords(pos.x() + radius, pos.y() + radius);
+                (min_x..=max_x).contains(&chunk_x) && (min_y..=max_y).contains(&chunk_y)
+            })
+        });
+    }
+
+    fn is_chunk_in_bounds(&self, chunk_x: i32, chunk_y: i32) -> bool {
+        match self.size {
+            Some(size) => {
+                let last = chunk_coords(size as i32 - 1, size as i32 - 1);
+                (0..=last.0).contains(&chunk_x) && (0..=last.1).contains(&chunk_y)
+            },
+            None => true
+        }
+    }
+
+    /// Where the animals of the prefabs in the chunks generated since the last call should
+    /// spawn. Each chunk only gives them once.
+    pub fn take_spawns(&mut self) -> Vec<Position> {
+        std::mem::take(&mut self.spawns)
+    }
+
+    fn load_chunk(&mut self, coords: (i32, i32)) -> &mut Chunk {
+        let (terrain, seed, spawns) = (&self.terrain, self.seed, &mut self.spawns);
+        self.chunks.entry(coords).or_insert_with(|| {
+            let (chunk, animals) = Chunk::generate(terrain, seed, coords);
+            spawns.extend(animals);
+            chunk
+        })
+    }
+}
+
+/// Borrowed rectangle of a [`GameMap`], from `left`, `top` included to `right`, `bottom` excluded.
+pub struct MapView<'a> {
+    map: &'a GameMap,
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32
+}
+
+impl<'a> MapView<'a> {
+    /// The tile at `x`, `y`, or `None` if it is outside the view, off the map or not loaded.
+    pub fn at(&self, x: i32, y: i32) -> Option<&'a Tile> {
+        if x < self.left || y < self.top || x >= self.right || y >= self.bottom {
+            return None
         }
-        portion
+        self.map.at(x, y)
     }
 
-    pub fn generate(&mut self, rng: &mut RNG) {
-        let mut tiles = Vec::with_capacity(MAP_SIZE*MAP_SIZE);
-        for i in 0..tiles.capacity() {
-            let position = self.index_to_position(i);
-            let mut tile = Tile::new(position.0, position.1);
-            if rng.gen_bool(0.6) {
-                tile.tile_type = TileType::Grass;
+    /// The loaded tiles in the view with their positions, going through each chunk only once.
+    pub fn iter(&self) -> impl Iterator<Item = (Position, &'a Tile)> {
+        let map = self.map;
+        let (left, top, right, bottom) = (self.left, self.top, self.right, self.bottom);
+        let (min_x, min_y) = chunk_coords(left, top);
+        let (max_x, max_y) = chunk_coords(right - 1, bottom - 1);
+        (min_x..=max_x).flat_map(move |chunk_x| (min_y..=max_y).map(move |chunk_y| (chunk_x, chunk_y)))
+            .filter_map(move |coords| map.chunks.get(&coords).map(|chunk| (coords, chunk)))
+            .flat_map(move |((chunk_x, chunk_y), chunk)| {
+                let xs = max(left, chunk_x * CHUNK_SIZE)..min(right, (chunk_x + 1) * CHUNK_SIZE);
+                let ys = max(top, chunk_y * CHUNK_SIZE)..min(bottom, (chunk_y + 1) * CHUNK_SIZE);
+                xs.flat_map(move |x| ys.clone().map(move |y| (x, y)))
+                    .filter(move |&(x, y)| map.is_in_bounds(x, y))
+                    .map(move |(x, y)| (Position::new(x, y), &chunk.tiles[Chunk::index(x, y)]))
+            })
+    }
+}
+
+// Only modified chunks are stored; the others are regenerated from the seed when loading.
+#[derive(Serialize, Deserialize)]
+struct PackedMap {
+    seed: u64,
+    size: Option<usize>,
+    chunks: Vec<PackedChunk>,
+    explored: Vec<PackedChunk>
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedChunk {
+    x: i32,
+    y: i32,
+    tiles: String
+}
+
+impl Serialize for GameMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Sorted so the same map always serializes, and hashes, the same
+        let mut coords: Vec<&(i32, i32)> = self.chunks.iter().filter(|(_, chunk)| chunk.modified).map(|(coords, _)| coords).collect();
+        coords.sort();
+        let chunks = coords.into_iter().map(|&(x, y)| {
+            let mut tiles = String::with_capacity((CHUNK_SIZE * CHUNK_SIZE * 2) as usize);
+            for tile in &self.chunks[&(x, y)].tiles {
+                write!(tiles, "{:02x}", tile.pack()).unwrap();
+            }
+            PackedChunk { x, y, tiles }
+        }).collect();
+        let mut coords: Vec<&(i32, i32)> = self.explored.keys().collect();
+        coords.sort();
+        let explored = coords.into_iter().map(|&(x, y)| {
+            let mut tiles = String::with_capacity((CHUNK_SIZE * CHUNK_SIZE / 4) as usize);
+            for bits in &self.explored[&(x, y)] {
+                write!(tiles, "{:016x}", bits).unwrap();
+            }
+            PackedChunk { x, y, tiles }
+        }).collect();
+        PackedMap { seed: self.seed, size: self.size, chunks, explored }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for GameMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<GameMap, D::Error> {
+        let packed = PackedMap::deserialize(deserializer)?;
+        let mut map = GameMap::new(packed.seed, packed.size);
+        for chunk in packed.chunks {
+            if chunk.tiles.len() != (CHUNK_SIZE * CHUNK_SIZE * 2) as usize {
+                return Err(D::Error::custom(format!("wrong number of tiles in chunk {} {}", chunk.x, chunk.y)))
             }
-            if rng.gen_bool(0.2) {
-                tile.variant = rng.gen_range(1, 5);
+            let mut tiles = Vec::with_capacity((CHUNK_SIZE * CHUNK_SIZE) as usize);
+            for i in 0..CHUNK_SIZE * CHUNK_SIZE {
+                let x = chunk.x * CHUNK_SIZE + i / CHUNK_SIZE;
+                let y = chunk.y * CHUNK_SIZE + i % CHUNK_SIZE;
+                let tile = chunk.tiles.get(i as usize * 2..i as usize * 2 + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .and_then(Tile::unpack)
+                    .ok_or_else(|| D::Error::custom(format!("invalid tile at {} {}", x, y)))?;
+                tiles.push(tile);
+            }
+            map.chunks.insert((chunk.x, chunk.y), Chunk { tiles, modified: true });
+        }
+        for chunk in packed.explored {
+            let mut bits = ExploredTiles::default();
+            if chunk.tiles.len() != bits.len() * 16 {
+                return Err(D::Error::custom(format!("wrong number of explored tiles in chunk {} {}", chunk.x, chunk.y)))
             }
-            tiles.push(tile);
+            for (i, word) in bits.iter_mut().enumerate() {
+                *word = chunk.tiles.get(i * 16..i * 16 + 16)
+                    .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| D::Error::custom(format!("invalid explored tiles in chunk {} {}", chunk.x, chunk.y)))?;
+            }
+            map.explored.insert((chunk.x, chunk.y), bits);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explored_map() -> GameMap {
+        let mut map = GameMap::new(7, None);
+        map.explore(3, 4);
+        map.explore(-40, 12);
+        map
+    }
+
+    #[test]
+    fn tiles_survive_packing() {
+        for index in 0..TileType::COUNT {
+            let tile_type = TileType::from_index(index).unwrap();
+            assert_eq!(tile_type.index(), index);
+            let tile = Tile::new(tile_type, 9);
+            assert_eq!(Tile::unpack(tile.pack()), Some(tile));
+            assert_eq!(tile.tile_type(), tile_type);
+            assert_eq!(tile.variant(), 9);
         }
-        self.tiles = tiles;
+        assert_eq!(Tile::unpack(0xf0), None);
+    }
+
+    #[test]
+    fn views_include_their_edges() {
+        let mut map = GameMap::new(7, None);
+        let center = Position::new(40, -3);
+        map.load_around(&center, 5);
+        let view = map.view_around(&center, 5);
+        assert_eq!(view.iter().count(), 11 * 11);
+        assert!(view.at(45, 2).is_some());
+        assert!(view.at(35, -8).is_some());
+        assert!(view.at(46, 2).is_none());
+    }
+
+    #[test]
+    fn explored_tiles_survive_a_round_trip() {
+        let json = serde_json::to_string(&explored_map()).unwrap();
+        let map: GameMap = serde_json::from_str(&json).unwrap();
+        assert!(map.is_explored(3, 4));
+        assert!(map.is_explored(-40, 12));
+        assert!(!map.is_explored(4, 4));
+    }
+
+    #[test]
+    fn non_ascii_explored_tiles_are_an_error() {
+        let mut value = serde_json::to_value(explored_map()).unwrap();
+        let tiles = value["explored"][0]["tiles"].as_str().unwrap();
+        // Same length in bytes, but with a character straddling the first word boundary
+        let corrupt = format!("{}é{}", &tiles[..15], &tiles[17..]);
+        value["explored"][0]["tiles"] = corrupt.into();
+        assert!(serde_json::from_value::<GameMap>(value).is_err());
+    }
+
+    #[test]
+    fn truncated_chunks_are_an_error() {
+        let mut map = explored_map();
+        map.load_around(&Position::new(0, 0), 1);
+        map.replace_tile(0, 0, Tile::new(TileType::StoneWall, 0));
+        let mut value = serde_json::to_value(&map).unwrap();
+        value["chunks"][0]["tiles"] = "00".into();
+        assert!(serde_json::from_value::<GameMap>(value).is_err());
+    }
+
+    fn sorted(positions: &[Position]) -> Vec<(i32, i32)> {
+        let mut coords: Vec<(i32, i32)> = positions.iter().map(|position| (position.x(), position.y())).collect();
+        coords.sort();
+        coords
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn prefabs_come_with_their_chunks() {
+        let center = Position::new(0, 0);
+        let mut map = GameMap::new(7, None);
+        map.load_around(&center, 200);
+        // Nothing else generates doors
+        assert!(map.iter().any(|(_, tile)| tile.tile_type() == TileType::Door));
+        let spawns = map.take_spawns();
+        assert!(!spawns.is_empty());
+        assert!(map.take_spawns().is_empty());
+        assert!(spawns.iter().all(|spawn| map.at(spawn.x(), spawn.y()).map(Tile::tile_type) == Some(TileType::Dirt)));
+        // Chunks with animals are kept, they would give them again otherwise
+        map.unload_far_from(&[]);
+        assert!(spawns.iter().all(|spawn| map.at(spawn.x(), spawn.y()).is_some()));
+        map.load_around(&center, 200);
+        assert!(map.take_spawns().is_empty());
+
+        let mut other = GameMap::new(7, None);
+        other.load_around(&center, 200);
+        assert_eq!(sorted(&other.take_spawns()), sorted(&spawns));
+    }
+}
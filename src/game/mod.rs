@@ -1,29 +1,60 @@
-use rustbox::{Key, RustBox};
 use rand::prelude::*;
 use rand_pcg::Pcg64;
 
-type RNG = Pcg64;
+/// Random number generator driving both world generation and the simulation.
+pub type GameRng = Pcg64;
 
 pub mod render;
+pub mod command;
+pub mod config;
+pub mod dungeon;
 pub mod animals;
-pub mod entity;
+pub mod components;
+pub mod level;
+pub mod fov;
+pub mod pathfinding;
 pub mod position;
+pub mod prefab;
 pub mod player;
 pub mod map;
+pub mod noise;
 pub mod rpg;
+pub mod registry;
+pub mod replay;
+pub mod save;
+pub mod spatial;
+pub mod surface;
+pub mod systems;
+pub mod terrain;
+pub mod world;
 
+use std::collections::BTreeMap;
 use std::fmt;
-use render::render;
-use entity::*;
-use player::*;
-use animals::*;
+use std::mem;
+use command::Command;
+use config::{ConfigError, GameConfig};
+use dungeon::{Dungeon, MapType};
+use level::{Level, Stairs};
+use registry::EntityId;
+use replay::Replay;
+use world::{EntityBuilder, World};
 use map::*;
 use position::*;
 
+/// How far from the camera, in tiles, the map is kept generated and drawn.
+pub const VIEW_RADIUS: i32 = 100;
+
+// Chunks stay loaded a bit past the view so walking back and forth doesn't regenerate them
+const UNLOAD_RADIUS: i32 = VIEW_RADIUS + 2 * CHUNK_SIZE;
+
+/// What the player's commands currently act on.
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum GameState {
+    /// Commands move the player around.
     Gameplay,
+    /// The game is over and the frontend should stop.
     Quit,
+    /// Commands move a cursor to look at tiles.
     InspectTiles
 }
 
@@ -33,57 +64,125 @@ impl fmt::Display for GameState {
     }
 }
 
+/// The whole simulation: map, entities and the RNG that drives them. The map and world are those
+/// of the level at `depth`, the other visited levels wait in `levels`.
 pub struct Game {
+    config: GameConfig,
     state: GameState,
-    player: Box<Player>,
-    entities: Vec<Box<dyn Entity>>,
-    rustbox: RustBox,
+    // The entity controlled through commands, it follows the player from level to level
+    player: EntityId,
+    // Where the cursor is while inspecting tiles
+    cursor: Position,
+    world: World,
     map: GameMap,
-    rng: RNG,
+    depth: usize,
+    stairs: Stairs,
+    levels: BTreeMap<usize, Level>,
+    next_depth: Option<usize>,
+    rng: GameRng,
     tick_count: usize,
-    last_input_key: Option<Key>
+    last_command: Option<Command>,
+    recording: Option<Replay>
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::new(&GameConfig::default()).expect("the default config is valid")
+    }
 }
 
 impl Game {
-    pub fn new() -> Game {
-        let rustbox = match RustBox::init(Default::default()) {
-            Result::Ok(v) => v,
-            Result::Err(e) => panic!("Failed: {}", e),
+    /// Generates a new world; the same config always produces the same world. Fails if the
+    /// config doesn't pass [`GameConfig::validate`].
+    pub fn new(config: &GameConfig) -> Result<Game, ConfigError> {
+        config.validate()?;
+        let mut rng = Pcg64::seed_from_u64(config.seed);
+        let mut map = GameMap::new(rng.gen(), config.map_size.or(config.map_type.default_size()));
+        let mut stairs = Stairs::default();
+        let start = match map.size() {
+            Some(size) if config.map_type != MapType::Overworld => {
+                let dungeon = Dungeon::generate(config.map_type, size, &mut rng).expect("the config was validated");
+                dungeon.stamp(&mut map, 0, 0);
+                stairs.down = Some(dungeon.farthest_from_spawn());
+                *dungeon.spawn_point()
+            },
+            _ => map.center()
         };
-        let map = GameMap::new();
-        let map_center = GameMap::center() as i32;
+        let mut world = World::new();
+        let player = world.spawn(player::player(start));
         let mut game = Game {
+            config: config.clone(),
             state: GameState::Gameplay,
-            player: Box::new(Player::new(map_center, map_center)),
-            rustbox,
-            rng: Pcg64::seed_from_u64(1234),
+            player,
+            cursor: start,
+            world,
+            rng,
             map,
-            entities: Vec::new(),
+            depth: 0,
+            stairs,
+            levels: BTreeMap::new(),
+            next_depth: None,
             tick_count: 0,
-            last_input_key: None
+            last_command: None,
+            recording: None
         };
-        // Generate Map
-        game.map.generate(&mut game.rng);
-        // Populate Entities
-        for _ in 0..10 {
-            let animal = Animal::new(map_center + game.rng.gen_range(-50, 50), map_center + game.rng.gen_range(-50, 50));
-            game.entities.push(Box::new(animal));
+        // Generate the area around the start
+        game.map.load_around(&start, VIEW_RADIUS);
+        if !game.map.at(start.x(), start.y()).is_some_and(Tile::is_passable) {
+            // Don't let the player start stuck in a wall or a lake
+            game.map.replace_tile(start.x(), start.y(), Tile::default());
         }
-        game
+        match game.stairs.down {
+            Some(down) => game.map.replace_tile(down.x(), down.y(), Tile::new(TileType::StairsDown, 0)),
+            None => game.add_stairs_down(&start)
+        }
+        // Populate Entities, skipping spots that are taken or off the map
+        level::spawn_animals(&mut game.world, &mut game.map, &start, config.animals, config.spawn_radius, &mut game.rng);
+        game.update_surroundings();
+        Ok(game)
     }
 
+    /// What the player's commands act on.
     pub fn current_state(&self) -> GameState {
         self.state
     }
 
+    /// The entity controlled through commands.
+    pub fn player(&self) -> EntityId {
+        self.player
+    }
+
+    /// The entities of the current level, the player included.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Tiles of the current level.
+    pub fn map(&self) -> &GameMap {
+        &self.map
+    }
+
+    /// How many levels below the surface the player is.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Where the stairs of the current level are.
+    pub fn stairs(&self) -> &Stairs {
+        &self.stairs
+    }
+
+    /// Where the player stands.
     pub fn player_position(&self) -> &Position {
-        self.player.current_position()
+        self.world.position(self.player).expect("the player is always on the map")
     }
 
+    /// Where the cursor is while inspecting tiles.
     pub fn cursor_position(&self) -> &Position {
-        self.player.cursor_position()
+        &self.cursor
     }
 
+    /// Where the view is centered: the cursor while inspecting tiles, the player otherwise.
     pub fn camera_position(&self) -> &Position {
         match self.state {
             GameState::InspectTiles => self.cursor_position(),
@@ -91,55 +190,278 @@ impl Game {
         }
     }
 
-    pub fn process_input(&mut self, key: &Key) {
-        self.last_input_key = Some(key.clone());
+    /// Queues the command the player will act on during the next [`Game::tick`].
+    pub fn process_input(&mut self, command: Command) {
+        if let Some(replay) = &mut self.recording {
+            replay.record(self.tick_count, command);
+        }
+        self.last_command = Some(command);
+    }
+
+    /// Records every following command into `replay`.
+    pub fn start_recording(&mut self, replay: Replay) {
+        self.recording = Some(replay);
     }
 
+    /// Stops recording, sealing the replay with the hash of the current state.
+    pub fn finish_recording(&mut self) -> Option<Replay> {
+        let mut replay = self.recording.take()?;
+        replay.set_final_hash(self.state_hash());
+        Some(replay)
+    }
+
+    /// Stops recording without sealing the replay, for when the state can't be trusted anymore,
+    /// like after a crash. Playing it back just runs the same commands again.
+    pub fn abandon_recording(&mut self) -> Option<Replay> {
+        self.recording.take()
+    }
+
+    /// How many ticks of gameplay went by since the game started.
     pub fn tick_count(&self) -> usize {
         self.tick_count
     }
 
-    pub fn entities_at(&self, position: &Position) -> Vec<&Box<dyn Entity>> {
-        self.entities.iter().filter(|entity| entity.current_position() == position).collect()
+    /// Adds an entity to the current level. If this happens during a tick, it acts from the next one.
+    pub fn spawn_entity(&mut self, entity: EntityBuilder) -> EntityId {
+        self.world.spawn(entity)
+    }
+
+    /// Takes an entity off the current level, returning its components. If this happens during
+    /// a tick before the entity acted, it doesn't. The player can't be removed.
+    pub fn remove_entity(&mut self, id: EntityId) -> Option<EntityBuilder> {
+        if id == self.player {
+            return None
+        }
+        self.world.despawn(id)
+    }
+
+    /// Moves the item `item` from the current level into the inventory of `carrier`. Returns
+    /// false if `item` isn't an item or `carrier` has nowhere to put it.
+    pub fn pick_up(&mut self, carrier: EntityId, item: EntityId) -> bool {
+        systems::pick_up(&mut self.world, carrier, item)
     }
 
+    /// Puts the item at `index` in the inventory of `carrier` down where it stands, returning
+    /// the ID it gets back.
+    pub fn drop_item(&mut self, carrier: EntityId, index: usize) -> Option<EntityId> {
+        systems::drop_item(&mut self.world, carrier, index)
+    }
+
+    /// Tile at `position` on the current level, if it's generated.
     pub fn tile_at(&self, position: &Position) -> Option<&Tile> {
         self.map.at(position.x(), position.y())
     }
 
+    /// Whether something could move to `position`: the tile allows it and nobody stands there.
     pub fn is_passable(&self, position: &Position) -> bool {
-        let tile = self.tile_at(position);
-        if tile.is_none() {
-            false
-        } else {
-            let no_entities = self.entities_at(position).is_empty() && self.player.current_position() != position;
-            tile.unwrap().is_passable() && no_entities
+        match self.tile_at(position) {
+            None => false,
+            Some(tile) => tile.is_passable() && !self.world.is_blocked(position)
+        }
+    }
+
+    /// Shortest route from `from` to `to` around walls and entities, without `from`. Only
+    /// tiles within `max_distance` steps are searched.
+    pub fn find_path(&self, from: &Position, to: &Position, max_distance: i32) -> Option<Vec<Position>> {
+        pathfinding::find_path(from, to, max_distance, |position| self.is_passable(position))
+    }
+
+    /// Lets the player act on the queued command, then the other entities for as long as it takes
+    /// the player to be ready again.
+    pub fn tick(&mut self) {
+        player::act(self);
+        systems::movement(self);
+        if let Some(depth) = self.next_depth.take() {
+            self.change_level(depth);
         }
+        if self.state == GameState::Gameplay {
+            systems::schedule(self);
+            self.tick_count += 1;
+        }
+        self.last_command = None;
+        self.update_surroundings();
     }
 
-    pub fn run(&mut self) {
-        while self.state != GameState::Quit {
-            render(&self.rustbox, &self);
-            match self.rustbox.poll_event(false) {
-                Ok(rustbox::Event::KeyEvent(key)) => {
-                    self.process_input(&key);
-                },
-                Err(e) => panic!("{}", e.to_string()),
-                _ => {}
+    // The way down from the overworld, a short walk away from `near`.
+    fn add_stairs_down(&mut self, near: &Position) {
+        self.stairs.down = level::open_tile_near(&self.map, near, 5, VIEW_RADIUS);
+        if let Some(down) = self.stairs.down {
+            self.map.replace_tile(down.x(), down.y(), Tile::new(TileType::StairsDown, 0));
+        }
+    }
+
+    // Puts the current level away and brings out the one at `depth`, generating it the first time,
+    // with the player on the stairs leading back.
+    fn change_level(&mut self, depth: usize) {
+        let going_down = depth > self.depth;
+        let mut player = self.world.take(self.player).expect("the player is always around");
+        let level = self.levels.remove(&depth).unwrap_or_else(|| Level::generate(&self.config, depth, self.player));
+        let previous = Level {
+            map: mem::replace(&mut self.map, level.map),
+            world: mem::replace(&mut self.world, level.world),
+            stairs: mem::replace(&mut self.stairs, level.stairs)
+        };
+        self.levels.insert(self.depth, previous);
+        self.depth = depth;
+        let arrival = if going_down { self.stairs.up } else { self.stairs.down };
+        if let Some(arrival) = arrival {
+            player.position = Some(arrival);
+        }
+        let arrived = self.world.spawn_as(self.player, player);
+        assert!(arrived, "every level keeps a slot for the player");
+    }
+
+    // Makes sure the view and every entity's surroundings are generated, with the animals of
+    // new prefabs, and lets go of the chunks nobody is near anymore. Entities move at most one tile per action, and act at most
+    // once more per tick than they are faster than the player, so the chunks within that reach
+    // are enough for the next one. Then looks around for the player, with the map all there,
+    // and remembers what it sees.
+    fn update_surroundings(&mut self) {
+        let (player, camera) = (*self.player_position(), *self.camera_position());
+        let mut anchors = vec![(player, UNLOAD_RADIUS), (camera, UNLOAD_RADIUS)];
+        self.map.load_around(&player, VIEW_RADIUS);
+        self.map.load_around(&camera, VIEW_RADIUS);
+        self.spawn_prefab_animals();
+        let player_speed = systems::speed(&self.world, self.player);
+        for (id, position) in self.world.positions() {
+            let reach = systems::speed(&self.world, id) / player_speed + 1;
+            self.map.load_around(position, reach);
+            anchors.push((*position, reach));
+        }
+        self.map.unload_far_from(&anchors);
+        systems::vision(&mut self.world, &self.map, self.player);
+        if let Some(vision) = self.world.vision.get(self.player) {
+            for position in vision.field_of_view.iter() {
+                self.map.explore(position.x(), position.y());
             }
-            let mut player = *self.player.clone();
-            player.tick(self);
-            self.player = Box::new(player);
-            if self.state == GameState::Gameplay {
-                let len = self.entities.len();
-                for i in 0..len {
-                    let mut entity = self.entities.pop().unwrap();
-                    entity.tick(self);
-                    self.entities.push(entity);
-                    self.entities.swap(i, len - 1)
+        }
+    }
+
+    // Prefabs come with the terrain of the overworld, dungeons are stamped over it and its
+    // prefabs. Spots that are taken by now are skipped.
+    fn spawn_prefab_animals(&mut self) {
+        let spawns = self.map.take_spawns();
+        if self.depth == 0 && self.config.map_type == MapType::Overworld {
+            for position in spawns {
+                if self.is_passable(&position) {
+                    self.world.spawn(animals::animal(position));
                 }
-                self.tick_count += 1;
             }
         }
     }
+
+    /// Shorthand for [`Game::process_input`] followed by [`Game::tick`].
+    pub fn step(&mut self, command: Command) {
+        self.process_input(command);
+        self.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small overworld without animals, with open ground all around the player so only what
+    // a test puts there is in the way
+    fn open_game() -> Game {
+        let config = GameConfig { seed: 7, map_size: Some(64), animals: 0, spawn_radius: 5, ..GameConfig::default() };
+        let mut game = Game::new(&config).unwrap();
+        let start = *game.player_position();
+        for dx in -3..=3 {
+            for dy in -3..=3 {
+                game.map.replace_tile(start.x() + dx, start.y() + dy, Tile::default());
+            }
+        }
+        game
+    }
+
+    fn offset(position: &Position, dx: i32, dy: i32) -> Position {
+        Position::new(position.x() + dx, position.y() + dy)
+    }
+
+    #[test]
+    fn moves_step_the_player_one_tick_at_a_time() {
+        let mut game = open_game();
+        let start = *game.player_position();
+        game.step(Command::Move(1, 0));
+        assert_eq!(*game.player_position(), offset(&start, 1, 0));
+        game.step(Command::Move(-1, 1));
+        assert_eq!(*game.player_position(), offset(&start, 0, 1));
+        game.step(Command::Wait);
+        assert_eq!(*game.player_position(), offset(&start, 0, 1));
+        assert_eq!(game.tick_count(), 3);
+        assert_eq!(game.current_state(), GameState::Gameplay);
+    }
+
+    #[test]
+    fn walls_and_entities_block_moves_but_items_dont() {
+        let mut game = open_game();
+        let start = *game.player_position();
+        let wall = offset(&start, 1, 0);
+        game.map.replace_tile(wall.x(), wall.y(), Tile::new(TileType::StoneWall, 0));
+        game.spawn_entity(EntityBuilder::new().at(offset(&start, 0, 1)));
+        game.spawn_entity(EntityBuilder::new().at(offset(&start, -1, 0)).with_item());
+        game.step(Command::Move(1, 0));
+        assert_eq!(*game.player_position(), start);
+        game.step(Command::Move(0, 1));
+        assert_eq!(*game.player_position(), start);
+        // Bumping into something still takes a turn
+        assert_eq!(game.tick_count(), 2);
+        game.step(Command::Move(-1, 0));
+        assert_eq!(*game.player_position(), offset(&start, -1, 0));
+    }
+
+    #[test]
+    fn inspecting_moves_the_cursor_without_time_passing() {
+        let mut game = open_game();
+        let start = *game.player_position();
+        game.step(Command::ToggleInspect);
+        assert_eq!(game.current_state(), GameState::InspectTiles);
+        assert_eq!(*game.cursor_position(), start);
+        game.step(Command::Move(1, 0));
+        game.step(Command::Move(1, 1));
+        assert_eq!(*game.player_position(), start);
+        assert_eq!(*game.camera_position(), offset(&start, 2, 1));
+        assert_eq!(game.tick_count(), 0);
+        game.step(Command::Cancel);
+        assert_eq!(game.current_state(), GameState::Gameplay);
+        assert_eq!(*game.camera_position(), start);
+        game.step(Command::Cancel);
+        assert_eq!(game.current_state(), GameState::Quit);
+    }
+
+    #[test]
+    fn quitting_works_from_any_state() {
+        let mut game = open_game();
+        game.step(Command::Quit);
+        assert_eq!(game.current_state(), GameState::Quit);
+        let mut game = open_game();
+        game.step(Command::ToggleInspect);
+        game.step(Command::Quit);
+        assert_eq!(game.current_state(), GameState::Quit);
+    }
+
+    #[test]
+    fn the_same_commands_lead_to_the_same_state() {
+        let config = GameConfig { seed: 11, map_size: Some(64), animals: 5, spawn_radius: 10, ..GameConfig::default() };
+        let commands = [Command::Move(1, 0), Command::Move(0, 1), Command::Wait, Command::Move(-1, -1), Command::Wait];
+        let hashes: Vec<u64> = (0..2).map(|_| {
+            let mut game = Game::new(&config).unwrap();
+            for command in &commands {
+                game.step(*command);
+            }
+            assert_eq!(game.tick_count(), commands.len());
+            game.state_hash()
+        }).collect();
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[test]
+    fn prefab_animals_only_spawn_on_the_overworld() {
+        let overworld = Game::new(&GameConfig { animals: 0, ..GameConfig::default() }).unwrap();
+        assert!(overworld.world.len() > 1);
+        let config = GameConfig { map_type: MapType::Caves, map_size: Some(64), animals: 0, ..GameConfig::default() };
+        let dungeon = Game::new(&config).unwrap();
+        assert_eq!(dungeon.world.len(), 1);
+    }
 }
@@ -0,0 +1,98 @@
+use rand::Rng;
+use super::GameRng;
+
+/// Smooth 2D value noise with values between 0 and 1, seeded from the game's RNG.
+pub struct Noise {
+    seed: u64
+}
+
+impl Noise {
+    /// Noise of its own, drawing its seed from `rng`.
+    pub fn new(rng: &mut GameRng) -> Noise {
+        Noise { seed: rng.gen() }
+    }
+
+    // Random value of a lattice point. The coordinates are hashed whole rather than looked up in
+    // a table, so the noise never repeats.
+    fn lattice(&self, x: i32, y: i32) -> f32 {
+        let mut hash = self.seed ^ ((x as u32 as u64) << 32 | y as u32 as u64);
+        // Finalizer of SplitMix64, every bit of the input affects every bit of the output
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+        // The top 24 bits, as many as an f32 holds exactly
+        (hash >> 40) as f32 / (1 << 24) as f32
+    }
+
+    /// Noise at `x`, `y`, blending the values of the four lattice points around it so it changes
+    /// smoothly from one to the next.
+    pub fn value(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = lerp(self.lattice(x0, y0), self.lattice(x0 + 1, y0), tx);
+        let bottom = lerp(self.lattice(x0, y0 + 1), self.lattice(x0 + 1, y0 + 1), tx);
+        lerp(top, bottom, ty)
+    }
+
+    /// Sum of `octaves` layers of noise, each with twice the detail and half the weight of the
+    /// previous one. `scale` is roughly the size in tiles of the biggest features.
+    pub fn fractal(&self, x: i32, y: i32, scale: f32, octaves: usize) -> f32 {
+        let mut total = 0.0;
+        let mut weight = 1.0;
+        let mut weights = 0.0;
+        let mut frequency = 1.0 / scale;
+        for octave in 0..octaves {
+            // Shift every octave so their lattice points don't line up
+            let offset = octave as f32 * 17.31;
+            total += self.value(x as f32 * frequency + offset, y as f32 * frequency + offset) * weight;
+            weights += weight;
+            weight *= 0.5;
+            frequency *= 2.0;
+        }
+        total / weights
+    }
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use super::*;
+
+    fn noise() -> Noise {
+        Noise::new(&mut GameRng::seed_from_u64(5))
+    }
+
+    #[test]
+    fn values_stay_between_0_and_1() {
+        let noise = noise();
+        for x in -50..50 {
+            for y in -50..50 {
+                let value = noise.value(x as f32 * 0.37, y as f32 * 0.61);
+                assert!((0.0..=1.0).contains(&value), "{} at {}, {}", value, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn lattice_points_do_not_repeat() {
+        let noise = noise();
+        // A table of 256 values wrapped every 256 lattice points, far or close to the origin
+        for &(x, y) in [(0, 0), (3, -7), (1_000_000, -2_000_000)].iter() {
+            for &period in [256, 512, 65536].iter() {
+                assert!(noise.lattice(x, y) != noise.lattice(x + period, y), "{}, {} + {}", x, y, period);
+                assert!(noise.lattice(x, y) != noise.lattice(x, y + period), "{}, {} + {}", x, y, period);
+            }
+        }
+        assert!(noise.lattice(0, 0) != Noise::new(&mut GameRng::seed_from_u64(6)).lattice(0, 0));
+        assert_eq!(noise.lattice(12, 34), Noise::new(&mut GameRng::seed_from_u64(5)).lattice(12, 34));
+    }
+}
@@ -0,0 +1,297 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use super::position::Position;
+
+/// Every step an entity can take, diagonals included. They all cost the same.
+pub const NEIGHBORS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+// How much further than the way to safety a fleeing entity is willing to go, as a fraction
+const FLEE_NUMERATOR: i32 = 6;
+const FLEE_DENOMINATOR: i32 = 5;
+
+// Square of side 2 * radius + 1 centered on a position, searches never leave it
+#[derive(Clone)]
+struct Area {
+    center: Position,
+    radius: i32
+}
+
+impl Area {
+    fn side(&self) -> i32 {
+        2 * self.radius.max(0) + 1
+    }
+
+    fn len(&self) -> usize {
+        (self.side() * self.side()) as usize
+    }
+
+    fn index(&self, position: &Position) -> Option<usize> {
+        let (dx, dy) = (position.x() - self.center.x() + self.radius, position.y() - self.center.y() + self.radius);
+        let side = self.side();
+        if dx >= 0 && dy >= 0 && dx < side && dy < side {
+            Some((dx * side + dy) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn position(&self, i: usize) -> Position {
+        let (i, side) = (i as i32, self.side());
+        Position::new(self.center.x() - self.radius + i / side, self.center.y() - self.radius + i % side)
+    }
+}
+
+// Number of steps between two positions when diagonals cost as much as straight moves
+fn steps_between(a: &Position, b: &Position) -> i32 {
+    let (dx, dy) = a.distance_parts_from(b);
+    dx.abs().max(dy.abs())
+}
+
+/// Shortest route from `from` to `to` with A*, without the starting position. Only tiles within
+/// `max_distance` steps of `from` are searched. `to` doesn't have to be passable itself, so
+/// the route can lead up to another entity.
+pub fn find_path<F: Fn(&Position) -> bool>(from: &Position, to: &Position, max_distance: i32, passable: F) -> Option<Vec<Position>> {
+    if from == to {
+        return Some(Vec::new())
+    }
+    let area = Area { center: *from, radius: max_distance };
+    let goal = area.index(to)?;
+    let start = area.index(from)?;
+    let mut costs = vec![i32::MAX; area.len()];
+    let mut came_from = vec![usize::MAX; area.len()];
+    let mut open = BinaryHeap::new();
+    costs[start] = 0;
+    // Ties go to whatever is closer to the goal, then to the lowest index so routes are stable
+    open.push(Reverse((steps_between(from, to), steps_between(from, to), start)));
+    while let Some(Reverse((_, _, current))) = open.pop() {
+        if current == goal {
+            let mut path = vec![area.position(goal)];
+            let mut i = goal;
+            while came_from[i] != start {
+                i = came_from[i];
+                path.push(area.position(i));
+            }
+            path.reverse();
+            return Some(path)
+        }
+        let position = area.position(current);
+        for (dx, dy) in &NEIGHBORS {
+            let next = Position::new(position.x() + dx, position.y() + dy);
+            let i = match area.index(&next) {
+                Some(i) => i,
+                None => continue
+            };
+            let cost = costs[current] + 1;
+            if cost < costs[i] && (i == goal || passable(&next)) {
+                costs[i] = cost;
+                came_from[i] = current;
+                let remaining = steps_between(&next, to);
+                open.push(Reverse((cost + remaining, remaining, i)));
+            }
+        }
+    }
+    None
+}
+
+/// Steps from every tile around a center to the nearest of some goals, following passable
+/// tiles. Walking downhill approaches the goals, and the map returned by
+/// [`DijkstraMap::flee`] leads away from them.
+#[derive(Clone)]
+pub struct DijkstraMap {
+    area: Area,
+    // i32::MAX where no goal could be reached
+    distances: Vec<i32>
+}
+
+impl DijkstraMap {
+    /// Fills the map within `radius` steps of `center`. Goals are included even if they aren't
+    /// passable, so the map can lead to entities.
+    pub fn new<F: Fn(&Position) -> bool>(goals: &[Position], center: &Position, radius: i32, passable: F) -> DijkstraMap {
+        let area = Area { center: *center, radius };
+        let seeds = goals.iter().filter_map(|goal| area.index(goal)).map(|i| (i, 0)).collect();
+        DijkstraMap::scan(area, seeds, passable)
+    }
+
+    // Spreads the seed values to every reachable tile, one step costing one
+    fn scan<F: Fn(&Position) -> bool>(area: Area, seeds: Vec<(usize, i32)>, passable: F) -> DijkstraMap {
+        let mut distances = vec![i32::MAX; area.len()];
+        let mut open = BinaryHeap::new();
+        for (i, value) in seeds {
+            if value < distances[i] {
+                distances[i] = value;
+                open.push(Reverse((value, i)));
+            }
+        }
+        while let Some(Reverse((value, current))) = open.pop() {
+            if value > distances[current] {
+                continue
+            }
+            let position = area.position(current);
+            for (dx, dy) in &NEIGHBORS {
+                let next = Position::new(position.x() + dx, position.y() + dy);
+                if let Some(i) = area.index(&next) {
+                    if value + 1 < distances[i] && passable(&next) {
+                        distances[i] = value + 1;
+                        open.push(Reverse((value + 1, i)));
+                    }
+                }
+            }
+        }
+        DijkstraMap { area, distances }
+    }
+
+    /// A map for running away from the goals of this one. Staying far from them is better, but
+    /// a fleeing entity would rather go around them towards open ground than into a dead end.
+    pub fn flee<F: Fn(&Position) -> bool>(&self, passable: F) -> DijkstraMap {
+        let seeds = self.distances.iter().enumerate()
+            .filter(|(_, distance)| **distance != i32::MAX)
+            .map(|(i, distance)| (i, -distance * FLEE_NUMERATOR / FLEE_DENOMINATOR))
+            .collect();
+        DijkstraMap::scan(self.area.clone(), seeds, passable)
+    }
+
+    /// Middle of the square the map covers.
+    pub fn center(&self) -> &Position {
+        &self.area.center
+    }
+
+    /// How far the map reaches from its center, in steps.
+    pub fn radius(&self) -> i32 {
+        self.area.radius
+    }
+
+    /// Value at `position`, or `None` if it's out of the map or no goal can be reached from it.
+    pub fn distance(&self, position: &Position) -> Option<i32> {
+        let i = self.area.index(position)?;
+        Some(self.distances[i]).filter(|distance| *distance != i32::MAX)
+    }
+
+    /// The neighbor of `from` with the lowest value, if it's lower than the value at `from`.
+    /// `from` itself may be missing from the map, like when an entity stands there.
+    pub fn downhill(&self, from: &Position) -> Option<Position> {
+        let here = self.distance(from).unwrap_or(i32::MAX);
+        let mut best: Option<(i32, Position)> = None;
+        for (dx, dy) in &NEIGHBORS {
+            let next = Position::new(from.x() + dx, from.y() + dy);
+            if let Some(distance) = self.distance(&next) {
+                if distance < here && best.is_none_or(|(lowest, _)| distance < lowest) {
+                    best = Some((distance, next));
+                }
+            }
+        }
+        best.map(|(_, position)| position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Passable unless it's a '#' or off the grid, with x going along the rows
+    fn grid(rows: &'static [&'static str]) -> impl Fn(&Position) -> bool {
+        move |position: &Position| {
+            rows.get(position.y() as usize).and_then(|row| row.as_bytes().get(position.x() as usize))
+                .is_some_and(|tile| position.x() >= 0 && position.y() >= 0 && *tile != b'#')
+        }
+    }
+
+    fn assert_walkable(from: &Position, path: &[Position], passable: impl Fn(&Position) -> bool) {
+        let mut previous = *from;
+        for step in path {
+            assert_eq!(steps_between(&previous, step), 1, "steps must be adjacent");
+            assert!(passable(step));
+            previous = *step;
+        }
+    }
+
+    const WALLED: &[&str] = &[
+        ".....",
+        ".###.",
+        "..#..",
+        ".###.",
+        "....."
+    ];
+
+    #[test]
+    fn paths_go_straight_on_open_ground() {
+        let path = find_path(&Position::new(0, 0), &Position::new(4, 2), 10, |_| true).unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.last(), Some(&Position::new(4, 2)));
+        assert_walkable(&Position::new(0, 0), &path, |_| true);
+        assert_eq!(find_path(&Position::new(3, 3), &Position::new(3, 3), 10, |_| true), Some(Vec::new()));
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let (from, to) = (Position::new(1, 2), Position::new(3, 2));
+        let path = find_path(&from, &to, 10, grid(WALLED)).unwrap();
+        // Out of the pocket, around the walls and into the other one
+        assert_eq!(path.len(), 6);
+        assert_eq!(path.last(), Some(&to));
+        assert_walkable(&from, &path, grid(WALLED));
+    }
+
+    #[test]
+    fn paths_stay_within_the_search_area() {
+        let (from, to) = (Position::new(1, 2), Position::new(3, 2));
+        assert_eq!(find_path(&from, &to, 1, grid(WALLED)), None);
+        assert_eq!(find_path(&Position::new(0, 0), &Position::new(9, 0), 5, |_| true), None);
+    }
+
+    #[test]
+    fn unreachable_goals_have_no_path() {
+        let sealed: &[&str] = &[
+            ".....",
+            ".###.",
+            ".#.#.",
+            ".###.",
+            "....."
+        ];
+        assert_eq!(find_path(&Position::new(0, 0), &Position::new(2, 2), 10, grid(sealed)), None);
+    }
+
+    #[test]
+    fn paths_can_end_on_blocked_goals() {
+        let path = find_path(&Position::new(0, 0), &Position::new(2, 0), 10, grid(&["..#"])).unwrap();
+        assert_eq!(path, vec![Position::new(1, 0), Position::new(2, 0)]);
+    }
+
+    #[test]
+    fn dijkstra_maps_count_steps_to_the_nearest_goal() {
+        let goals = [Position::new(0, 0), Position::new(4, 4)];
+        let map = DijkstraMap::new(&goals, &Position::new(2, 2), 2, grid(WALLED));
+        assert_eq!((*map.center(), map.radius()), (Position::new(2, 2), 2));
+        assert_eq!(map.distance(&Position::new(0, 0)), Some(0));
+        assert_eq!(map.distance(&Position::new(4, 0)), Some(4));
+        assert_eq!(map.distance(&Position::new(0, 3)), Some(3));
+        // Walls, pockets only reachable through walls and tiles out of the map
+        assert_eq!(map.distance(&Position::new(2, 1)), None);
+        assert_eq!(map.distance(&Position::new(9, 9)), None);
+    }
+
+    #[test]
+    fn going_downhill_reaches_a_goal() {
+        let map = DijkstraMap::new(&[Position::new(4, 4)], &Position::new(2, 2), 2, grid(WALLED));
+        let mut position = Position::new(1, 2);
+        let mut steps = 0;
+        while let Some(next) = map.downhill(&position) {
+            assert!(grid(WALLED)(&next));
+            position = next;
+            steps += 1;
+        }
+        assert_eq!(position, Position::new(4, 4));
+        assert_eq!(Some(steps), DijkstraMap::new(&[Position::new(1, 2)], &Position::new(2, 2), 2, grid(WALLED)).distance(&position));
+    }
+
+    #[test]
+    fn fleeing_leads_away_from_the_goals() {
+        let threat = Position::new(0, 0);
+        let map = DijkstraMap::new(&[threat], &threat, 6, |_| true).flee(|_| true);
+        let mut position = Position::new(1, 1);
+        for _ in 0..4 {
+            let next = map.downhill(&position).unwrap();
+            assert!(steps_between(&next, &threat) > steps_between(&position, &threat));
+            position = next;
+        }
+    }
+}
@@ -1,109 +1,83 @@
 use super::position::*;
 use super::{Game, GameState};
-use super::Entity;
 use super::render::RenderInfo;
 use super::rpg::CharacterStats;
-use rustbox::{Color,Key};
+use super::surface::Color;
+use super::command::Command;
+use super::components::{Name, WantsToMove};
+use super::map::TileType;
+use super::world::EntityBuilder;
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone)]
-pub struct Player {
-    position: Position,
-    cursor_position: Position,
-    render_info: RenderInfo,
-    character_stats: CharacterStats
+/// Marks the entity controlled through [`Command`]s.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Player {}
+
+/// The character the game starts with, standing at `position`.
+pub fn player(position: Position) -> EntityBuilder {
+    EntityBuilder::new()
+        .at(position)
+        .with_render_info(RenderInfo::new('@', Color::White))
+        .with_name(Name::new(Some("Player"), "Human"))
+        .with_stats(CharacterStats::new(5, 5, 30))
+        .with_vision()
+        .with_energy()
+        .with_inventory()
+        .with_player()
 }
 
-impl Player {
-    pub fn new(x: i32, y: i32) -> Player {
-        Player {
-            character_stats: CharacterStats::new(5, 5, 30),
-            position: Position::new(x, y),
-            cursor_position: Position::new(x, y),
-            render_info: RenderInfo::new('@', Color::White)
+/// Acts on the command the player gave for this tick.
+pub fn act(game: &mut Game) {
+    let command = match game.last_command {
+        Some(command) => command,
+        None => return
+    };
+    match command {
+        Command::Move(h, v) => mov(h, v, game),
+        Command::Wait => {},
+        Command::Descend => take_stairs(TileType::StairsDown, game),
+        Command::Ascend => take_stairs(TileType::StairsUp, game),
+        Command::Cancel => {
+            if game.state == GameState::Gameplay {
+                game.state = GameState::Quit;
+            } else {
+                game.state = GameState::Gameplay;
+            }
+        },
+        Command::Quit => {
+            game.state = GameState::Quit;
+        },
+        Command::ToggleInspect => {
+            game.state = match game.state {
+                GameState::InspectTiles => GameState::Gameplay,
+                _ => {
+                    game.cursor = *game.player_position();
+                    GameState::InspectTiles
+                }
+            }
         }
     }
+}
 
-    pub fn cursor_position(&self) -> &Position {
-        &self.cursor_position
-    }
-
-    fn mov(&mut self, h: i32, v: i32, game: &Game) -> bool {
-        let position = match game.state {
-            GameState::InspectTiles => &mut self.cursor_position,
-            _ => &mut self.position
+fn take_stairs(stairs: TileType, game: &mut Game) {
+    let on_stairs = game.tile_at(game.player_position()).is_some_and(|tile| tile.tile_type() == stairs);
+    if game.state == GameState::Gameplay && on_stairs {
+        game.next_depth = match stairs {
+            TileType::StairsDown => Some(game.depth + 1),
+            _ => game.depth.checked_sub(1)
         };
-        match game.current_state() {
-            GameState::Gameplay => {
-                position.move_relative_if_passable(h, v, game)
-            },
-            GameState::InspectTiles => {
-                position.move_relative(h, v);
-                true
-            },
-            _ => false
-        }
     }
 }
 
-impl Entity for Player {
-    fn current_position(&self) -> &Position {
-        &self.position
-    }
-
-    fn position(&mut self) -> &mut Position {
-        &mut self.position
-    }
-
-    fn render_info(&self) -> &RenderInfo {
-        &self.render_info
-    }
-
-    fn name(&self) -> Option<&str> {
-        Some("Player")
+// Steps the player, or the cursor while inspecting tiles.
+fn mov(h: i32, v: i32, game: &mut Game) {
+    match game.state {
+        GameState::Gameplay => {
+            let mut destination = *game.player_position();
+            destination.move_relative(h, v);
+            game.world.wants_to_move.insert(game.player, WantsToMove(destination));
+        },
+        GameState::InspectTiles => game.cursor.move_relative(h, v),
+        _ => {}
     }
-
-    fn kind(&self) -> &str {
-        "Human"
-    }
-
-    fn stats(&self) -> &CharacterStats {
-        &self.character_stats
-    }
-
-    fn tick(&mut self, game: &mut Game) {
-        match game.last_input_key {
-            None => {},
-            Some(key) => match key {
-                Key::Left => {
-                    self.mov(-1, 0, game);
-                }
-                Key::Right => {
-                    self.mov(1, 0, game);
-                }
-                Key::Up => {
-                    self.mov(0, -1, game);
-                }
-                Key::Down => {
-                    self.mov(0, 1, game);
-                },
-                Key::Esc => {
-                    if game.state == GameState::Gameplay {
-                        game.state = GameState::Quit;
-                    } else {
-                        game.state = GameState::Gameplay;
-                    }
-                },
-                Key::Char('v') => {
-                    game.state = match game.state {
-                        GameState::InspectTiles => GameState::Gameplay,
-                        _ => {
-                            self.cursor_position = self.position.clone();
-                            GameState::InspectTiles
-                        }
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-}
\ No newline at end of file
+}
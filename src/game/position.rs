@@ -1,30 +1,37 @@
-use std::cmp::{min,max};
 use super::Game;
+use serde::{Serialize, Deserialize};
 
-#[derive(Copy, Clone, PartialEq)]
+/// Coordinates of a tile on the map, `y` growing downwards.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct Position {
     x: i32,
     y: i32
 }
 
 impl Position {
+    /// The position at `x`, `y`.
     pub fn new(x: i32, y: i32) -> Position {
         Position { x, y }
     }
 
+    /// Column, growing to the right.
     pub fn x(&self) -> i32 {
         self.x
     }
 
+    /// Row, growing downwards.
     pub fn y(&self) -> i32 {
         self.y
     }
 
+    /// Moves by `h` tiles horizontally and `v` vertically.
     pub fn move_relative(&mut self, h: i32, v: i32) {
-        self.x = self.x + h;
-        self.y = self.y + v;
+        self.x += h;
+        self.y += v;
     }
 
+    /// Moves like [`Position::move_relative`] if the destination is passable in `game`,
+    /// returning whether it did.
     pub fn move_relative_if_passable(&mut self, h: i32, v: i32, game: &Game) -> bool {
         let destination = Position::new(self.x + h, self.y + v);
         if game.is_passable(&destination) {
@@ -36,19 +43,22 @@ impl Position {
         }
     }
 
+    /// Horizontal and vertical offsets from here to `position`.
     pub fn distance_parts_from(&self, position: &Position) -> (i32, i32) {
         let dx = position.x() - self.x;
         let dy = position.y() - self.y;
         (dx, dy)
     }
 
+    /// Straight line distance to `position`.
     pub fn distance_from(&self, position: &Position) -> f32 {
         let distance = self.distance_parts_from(position);
         ((distance.0.pow(2) + distance.1.pow(2)) as f32).sqrt()
     }
 
+    /// One step (-1, 0 or 1 on each axis) towards `position`.
     pub fn direction_for(&self, position: &Position) -> (i32, i32) {
         let distance = self.distance_parts_from(position);
-        (max(-1, min(1, distance.0)), max(-1, min(1, distance.1)))
+        (distance.0.clamp(-1, 1), distance.1.clamp(-1, 1))
     }
 }
\ No newline at end of file
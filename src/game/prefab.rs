@@ -0,0 +1,234 @@
+use rand::Rng;
+use super::GameRng;
+use super::map::TileType;
+use super::position::Position;
+
+// Templates shipped with the game, as name and text
+const BUILTIN: &[(&str, &str)] = &[
+    ("shrine", include_str!("prefabs/shrine.txt")),
+    ("camp", include_str!("prefabs/camp.txt")),
+    ("ruin", include_str!("prefabs/ruin.txt"))
+];
+
+/// What a character of a template puts on the map.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PrefabCell {
+    /// A tile replacing the terrain.
+    Tile(TileType),
+    /// An animal standing on dirt.
+    Animal
+}
+
+impl PrefabCell {
+    /// Reads a template character. Spaces leave the terrain as it is and give `Ok(None)`.
+    pub fn from_char(c: char) -> Result<Option<PrefabCell>, String> {
+        let cell = match c {
+            ' ' => return Ok(None),
+            '#' => PrefabCell::Tile(TileType::StoneWall),
+            '.' => PrefabCell::Tile(TileType::Floor),
+            ',' => PrefabCell::Tile(TileType::Dirt),
+            '"' => PrefabCell::Tile(TileType::Grass),
+            '+' => PrefabCell::Tile(TileType::Door),
+            '~' => PrefabCell::Tile(TileType::ShallowWater),
+            'T' => PrefabCell::Tile(TileType::Tree),
+            'o' => PrefabCell::Tile(TileType::Boulder),
+            'a' => PrefabCell::Animal,
+            _ => return Err(format!("unknown character '{}'", c))
+        };
+        Ok(Some(cell))
+    }
+}
+
+/// How a template is turned before being stamped: `rotation` quarter turns clockwise, then
+/// flipped left to right if `mirrored`.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Orientation {
+    /// Quarter turns clockwise, from 0 to 3.
+    pub rotation: u8,
+    /// Whether it's flipped left to right after turning.
+    pub mirrored: bool
+}
+
+impl Orientation {
+    /// Any of the eight orientations, equally likely.
+    pub fn random(rng: &mut GameRng) -> Orientation {
+        Orientation { rotation: rng.gen_range(0, 4), mirrored: rng.gen_bool(0.5) }
+    }
+}
+
+/// A hand-authored map fragment, read from a text template where each character is a tile
+/// or an entity to spawn.
+pub struct Prefab {
+    name: String,
+    width: i32,
+    height: i32,
+    cells: Vec<Option<PrefabCell>>
+}
+
+impl Prefab {
+    /// Reads a template. Lines shorter than the longest one are padded with spaces.
+    pub fn parse(name: &str, source: &str) -> Result<Prefab, String> {
+        let lines: Vec<&str> = source.lines().collect();
+        let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        if width == 0 {
+            return Err(format!("prefab '{}' is empty", name))
+        }
+        let mut rows = Vec::with_capacity(lines.len());
+        for line in &lines {
+            let mut row = Vec::with_capacity(width);
+            for c in line.chars() {
+                row.push(PrefabCell::from_char(c).map_err(|e| format!("prefab '{}': {}", name, e))?);
+            }
+            row.resize(width, None);
+            rows.push(row);
+        }
+        // Stored column by column, like the map
+        let mut cells = Vec::with_capacity(width * rows.len());
+        for x in 0..width {
+            for row in &rows {
+                cells.push(row[x]);
+            }
+        }
+        Ok(Prefab { name: name.to_string(), width: width as i32, height: rows.len() as i32, cells })
+    }
+
+    /// The templates that come with the game.
+    pub fn builtin() -> Vec<Prefab> {
+        BUILTIN.iter().map(|(name, source)| Prefab::parse(name, source).expect("built-in prefabs are valid")).collect()
+    }
+
+    /// What the template is called, like "ruin".
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Width and height once turned to `orientation`.
+    pub fn size(&self, orientation: Orientation) -> (i32, i32) {
+        if orientation.rotation.is_multiple_of(2) { (self.width, self.height) } else { (self.height, self.width) }
+    }
+
+    /// The cell at `x`, `y` of the template turned to `orientation`.
+    pub fn cell(&self, x: i32, y: i32, orientation: Orientation) -> Option<PrefabCell> {
+        let (width, height) = self.size(orientation);
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return None
+        }
+        let (mut x, mut y) = (if orientation.mirrored { width - 1 - x } else { x }, y);
+        // Undo the quarter turns one at a time
+        let (mut width, mut height) = (width, height);
+        for _ in 0..orientation.rotation % 4 {
+            let (previous_x, previous_y) = (y, width - 1 - x);
+            x = previous_x;
+            y = previous_y;
+            std::mem::swap(&mut width, &mut height);
+        }
+        self.cells[(x * self.height + y) as usize]
+    }
+
+    /// Whether the template fits with its top left corner at `left`, `top`, `tile_at` telling
+    /// what lies at a position if it's available. Everything it would change must be open ground
+    /// or vegetation, so it never cuts into water, rock or other buildings.
+    pub fn fits<F: Fn(i32, i32) -> Option<TileType>>(&self, tile_at: F, left: i32, top: i32, orientation: Orientation) -> bool {
+        let (width, height) = self.size(orientation);
+        (0..width).all(|x| (0..height).all(|y| {
+            self.cell(x, y, orientation).is_none() || tile_at(left + x, top + y).is_some_and(|tile_type| matches!(
+                tile_type,
+                TileType::Dirt | TileType::Grass | TileType::Sand | TileType::Mud | TileType::Tree | TileType::Boulder
+            ))
+        }))
+    }
+
+    /// Writes the template through `set_tile` and returns where its animals should spawn.
+    pub fn stamp<F: FnMut(i32, i32, TileType)>(&self, mut set_tile: F, left: i32, top: i32, orientation: Orientation) -> Vec<Position> {
+        let (width, height) = self.size(orientation);
+        let mut spawns = Vec::new();
+        for x in 0..width {
+            for y in 0..height {
+                let tile_type = match self.cell(x, y, orientation) {
+                    None => continue,
+                    Some(PrefabCell::Tile(tile_type)) => tile_type,
+                    Some(PrefabCell::Animal) => {
+                        spawns.push(Position::new(left + x, top + y));
+                        TileType::Dirt
+                    }
+                };
+                set_tile(left + x, top + y, tile_type);
+            }
+        }
+        spawns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Uneven on both axes, so every orientation comes out different
+    const TEMPLATE: &str = "#.+\n,a";
+
+    fn to_char(cell: Option<PrefabCell>) -> char {
+        match cell {
+            None => ' ',
+            Some(PrefabCell::Animal) => 'a',
+            Some(PrefabCell::Tile(tile_type)) => match tile_type {
+                TileType::StoneWall => '#',
+                TileType::Floor => '.',
+                TileType::Dirt => ',',
+                TileType::Door => '+',
+                _ => '?'
+            }
+        }
+    }
+
+    fn rows(prefab: &Prefab, orientation: Orientation) -> Vec<String> {
+        let (width, height) = prefab.size(orientation);
+        (0..height).map(|y| (0..width).map(|x| to_char(prefab.cell(x, y, orientation))).collect()).collect()
+    }
+
+    #[test]
+    fn templates_turn_and_mirror() {
+        let prefab = Prefab::parse("test", TEMPLATE).unwrap();
+        let expected: [(u8, bool, &[&str]); 8] = [
+            (0, false, &["#.+", ",a "]),
+            (1, false, &[",#", "a.", " +"]),
+            (2, false, &[" a,", "+.#"]),
+            (3, false, &["+ ", ".a", "#,"]),
+            (0, true, &["+.#", " a,"]),
+            (1, true, &["#,", ".a", "+ "]),
+            (2, true, &[",a ", "#.+"]),
+            (3, true, &[" +", "a.", ",#"])
+        ];
+        for (rotation, mirrored, tiles) in expected.iter() {
+            let orientation = Orientation { rotation: *rotation, mirrored: *mirrored };
+            assert_eq!(rows(&prefab, orientation), *tiles, "{:?}", orientation);
+        }
+        assert_eq!(prefab.cell(3, 0, Orientation::default()), None);
+        assert_eq!(prefab.cell(0, -1, Orientation::default()), None);
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        let error = Prefab::parse("broken", "#.#\n#x#").err().unwrap();
+        assert!(error.contains("broken") && error.contains("'x'"), "{}", error);
+        assert!(Prefab::parse("empty", "").is_err());
+        assert!(Prefab::parse("blank", "\n\n").is_err());
+        assert_eq!(Prefab::builtin().len(), BUILTIN.len());
+    }
+
+    #[test]
+    fn stamps_only_what_fits() {
+        let prefab = Prefab::parse("test", TEMPLATE).unwrap();
+        let orientation = Orientation { rotation: 1, mirrored: false };
+        // The space of the template may cover water, the rest needs open ground
+        let water_at_space = |x, y| Some(if (x, y) == (10, 22) { TileType::DeepWater } else { TileType::Grass });
+        assert!(prefab.fits(water_at_space, 10, 20, orientation));
+        assert!(!prefab.fits(water_at_space, 9, 20, orientation));
+        assert!(!prefab.fits(|_, _| None, 10, 20, orientation));
+        let mut stamped = Vec::new();
+        let spawns = prefab.stamp(|x, y, tile_type| stamped.push((x, y, tile_type)), 10, 20, orientation);
+        assert_eq!(spawns, vec![Position::new(10, 21)]);
+        assert_eq!(stamped.len(), 5);
+        assert!(stamped.contains(&(10, 21, TileType::Dirt)));
+        assert!(stamped.contains(&(11, 22, TileType::Door)));
+    }
+}
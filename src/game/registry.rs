@@ -0,0 +1,255 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+
+/// Handle to an entity of a level that stays valid as other entities come and go. Slots are
+/// reused once their entity is removed, but with a new generation, so an old ID never points
+/// to the entity that took its place.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub struct EntityId {
+    index: u32,
+    generation: u32
+}
+
+impl EntityId {
+    /// Slot of the entity, reused by others once it's gone.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// How many entities had the slot before this one.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// What an entity slot of a [`World`](super::world::World) is used for.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SlotState {
+    /// Waiting for the next entity to be spawned.
+    Free,
+    /// Taken by a living entity.
+    Alive,
+    /// Kept for an entity that's away, like the player on another level, so its ID still
+    /// points to it when it comes back.
+    Reserved
+}
+
+struct Slot {
+    generation: u32,
+    state: SlotState
+}
+
+/// Hands out the [`EntityId`]s of a level and knows which ones are still alive. The components
+/// of each entity are kept by the [`World`](super::world::World) around it.
+#[derive(Default)]
+pub struct EntityRegistry {
+    slots: Vec<Slot>,
+    // Lowest first, so which slot gets reused doesn't depend on the order of removals
+    free: BTreeSet<u32>,
+    len: usize
+}
+
+impl EntityRegistry {
+    /// A registry without any slot.
+    pub fn new() -> EntityRegistry {
+        EntityRegistry::default()
+    }
+
+    /// Rebuilds a registry from the generation and state of every slot, as listed by
+    /// [`EntityRegistry::slots`].
+    pub fn from_slots<I: Iterator<Item = (u32, SlotState)>>(slots: I) -> EntityRegistry {
+        let mut registry = EntityRegistry::new();
+        for (index, (generation, state)) in slots.enumerate() {
+            match state {
+                SlotState::Free => { registry.free.insert(index as u32); },
+                SlotState::Alive => registry.len += 1,
+                SlotState::Reserved => {}
+            }
+            registry.slots.push(Slot { generation, state });
+        }
+        registry
+    }
+
+    /// Generation and state of every slot, in order.
+    pub fn slots(&self) -> impl Iterator<Item = (u32, SlotState)> + '_ {
+        self.slots.iter().map(|slot| (slot.generation, slot.state))
+    }
+
+    /// How many entities are alive, leaving out reserved slots.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no entity is alive.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hands out the ID of a new entity, reusing the lowest free slot with its next generation.
+    pub fn spawn(&mut self) -> EntityId {
+        self.len += 1;
+        match self.free.pop_first() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.state = SlotState::Alive;
+                EntityId { index, generation: slot.generation }
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, state: SlotState::Alive });
+                EntityId { index: self.slots.len() as u32 - 1, generation: 0 }
+            }
+        }
+    }
+
+    /// Frees the slot of `id`, returning false if it was already gone.
+    pub fn remove(&mut self, id: EntityId) -> bool {
+        if !self.contains(id) {
+            return false
+        }
+        let slot = &mut self.slots[id.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.state = SlotState::Free;
+        self.free.insert(id.index);
+        self.len -= 1;
+        true
+    }
+
+    /// Sets the slot of `id` aside for it, whether it lives here and is leaving or comes from
+    /// another registry and may arrive later. Fails if someone else has the slot, or had it
+    /// with a later generation, so no stale ID can point to `id`.
+    pub fn reserve(&mut self, id: EntityId) -> bool {
+        while self.slots.len() <= id.index as usize {
+            self.free.insert(self.slots.len() as u32);
+            self.slots.push(Slot { generation: 0, state: SlotState::Free });
+        }
+        let slot = &self.slots[id.index as usize];
+        match slot.state {
+            SlotState::Alive if slot.generation == id.generation => self.len -= 1,
+            SlotState::Reserved if slot.generation == id.generation => {},
+            SlotState::Free if slot.generation <= id.generation => { self.free.remove(&id.index); },
+            _ => return false
+        }
+        self.slots[id.index as usize] = Slot { generation: id.generation, state: SlotState::Reserved };
+        true
+    }
+
+    /// Brings `id` into its slot, reserving it first if needed. Fails like [`EntityRegistry::reserve`].
+    pub fn claim(&mut self, id: EntityId) -> bool {
+        if self.contains(id) || !self.reserve(id) {
+            return false
+        }
+        self.slots[id.index as usize].state = SlotState::Alive;
+        self.len += 1;
+        true
+    }
+
+    /// Whether `id` is alive, false once its slot was freed even if it was handed out again.
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.slots.get(id.index as usize).is_some_and(|slot| slot.state == SlotState::Alive && slot.generation == id.generation)
+    }
+
+    /// Whether the slot of `id` is set aside for it while it's away.
+    pub fn is_reserved(&self, id: EntityId) -> bool {
+        self.slots.get(id.index as usize).is_some_and(|slot| slot.state == SlotState::Reserved && slot.generation == id.generation)
+    }
+
+    /// IDs of every entity, in slot order.
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.slots.iter().enumerate()
+            .filter(|(_, slot)| slot.state == SlotState::Alive)
+            .map(|(index, slot)| EntityId { index: index as u32, generation: slot.generation })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(index: u32, generation: u32) -> EntityId {
+        EntityId { index, generation }
+    }
+
+    #[test]
+    fn removed_slots_come_back_with_a_new_generation() {
+        let mut registry = EntityRegistry::new();
+        let ids: Vec<EntityId> = (0..3).map(|_| registry.spawn()).collect();
+        assert_eq!(ids, vec![id(0, 0), id(1, 0), id(2, 0)]);
+        assert!(registry.remove(ids[2]));
+        assert!(registry.remove(ids[0]));
+        assert!(!registry.remove(ids[0]));
+        assert_eq!(registry.len(), 1);
+        // The lowest free slot is reused first, whatever the order of removals
+        let reused = registry.spawn();
+        assert_eq!(reused, id(0, 1));
+        assert!(registry.contains(reused));
+        assert!(!registry.contains(ids[0]));
+        assert_eq!(registry.spawn(), id(2, 1));
+        assert_eq!(registry.spawn(), id(3, 0));
+        assert_eq!(registry.ids(), vec![id(0, 1), id(1, 0), id(2, 1), id(3, 0)]);
+    }
+
+    #[test]
+    fn reserved_slots_wait_for_their_entity() {
+        let mut registry = EntityRegistry::new();
+        let leaving = registry.spawn();
+        let staying = registry.spawn();
+        assert!(registry.reserve(leaving));
+        assert!(!registry.contains(leaving));
+        assert!(registry.is_reserved(leaving));
+        assert_eq!(registry.len(), 1);
+        // Nobody else gets the slot in the meantime
+        assert_eq!(registry.spawn(), id(2, 0));
+        assert!(!registry.reserve(id(0, 1)));
+        assert!(registry.contains(staying));
+        assert!(registry.claim(leaving));
+        assert!(!registry.claim(leaving));
+        assert!(registry.contains(leaving));
+        assert_eq!(registry.len(), 3);
+    }
+
+    #[test]
+    fn reserving_makes_room_for_entities_from_elsewhere() {
+        let mut registry = EntityRegistry::new();
+        registry.spawn();
+        assert!(registry.reserve(id(3, 2)));
+        assert_eq!(registry.spawn(), id(1, 0));
+        assert_eq!(registry.spawn(), id(2, 0));
+        assert_eq!(registry.spawn(), id(4, 0));
+        assert!(registry.claim(id(3, 2)));
+        assert_eq!(registry.len(), 5);
+    }
+
+    #[test]
+    fn taken_or_newer_slots_cant_be_reserved() {
+        let mut registry = EntityRegistry::new();
+        let first = registry.spawn();
+        assert!(!registry.reserve(id(0, 1)));
+        assert!(!registry.claim(id(0, 1)));
+        registry.remove(first);
+        let second = registry.spawn();
+        registry.remove(second);
+        // The slot had generation 1 since, an ID from before it would be stale
+        assert!(!registry.reserve(first));
+        assert!(registry.reserve(id(0, 2)));
+        assert!(!registry.reserve(id(0, 3)));
+    }
+
+    #[test]
+    fn slots_survive_a_round_trip() {
+        let mut registry = EntityRegistry::new();
+        let ids: Vec<EntityId> = (0..4).map(|_| registry.spawn()).collect();
+        registry.remove(ids[1]);
+        registry.remove(ids[3]);
+        registry.reserve(ids[2]);
+        let slots: Vec<(u32, SlotState)> = registry.slots().collect();
+        assert_eq!(slots, vec![(0, SlotState::Alive), (1, SlotState::Free), (0, SlotState::Reserved), (1, SlotState::Free)]);
+        let mut restored = EntityRegistry::from_slots(slots.into_iter());
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored.ids(), registry.ids());
+        assert!(restored.is_reserved(ids[2]));
+        assert_eq!(restored.spawn(), id(1, 1));
+        assert_eq!(restored.spawn(), id(3, 1));
+        assert_eq!(restored.spawn(), id(4, 0));
+    }
+}
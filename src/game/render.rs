@@ -1,152 +1,189 @@
-use rustbox::{Color, RustBox};
+use std::cmp::{min, max};
 use super::GameState;
-use super::{Game,Position,Entity};
+use super::{Game,Position};
+use super::registry::EntityId;
 use super::rpg::CharacterStats;
+use super::surface::{Color, Style, Surface};
+use serde::{Serialize, Deserialize};
 
 const SIDE_PANEL_WIDTH: usize = 32;
 
-#[derive(Copy, Clone, PartialEq)]
+/// How an entity looks on the map.
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RenderInfo {
+    /// Drawn on the entity's tile.
     pub character: char,
+    /// Foreground color of the character.
     pub color: Color
 }
 
 impl RenderInfo {
+    /// Looks like `character` in `color`.
     pub fn new(character: char, color: Color) -> RenderInfo {
         RenderInfo { character, color }
     }
 }
 
-pub fn render(rustbox: &RustBox, game: &super::Game) {
-    rustbox.clear();
+/// Draws the part of the world around the camera and the side panel, then presents the surface.
+pub fn render(surface: &mut dyn Surface, game: &super::Game) {
+    surface.clear();
     let camera_position = game.camera_position();
-    render_map(rustbox, camera_position, &game.player, &game.map);
-    render_player(rustbox, camera_position, game.player_position());
-    for i in 0..game.entities.len() {
-        let entity = &*game.entities[i];
-        if game.player.can_see(entity.current_position()) {
-            render_entity(rustbox, camera_position, entity);
-        }
+    render_map(surface, camera_position, game);
+    // Nothing the player can see is further away than its field of view reaches
+    let radius = game.world.vision.get(game.player).map_or(0, |vision| vision.field_of_view.radius());
+    let visible: Vec<EntityId> = game.world.entities_within(game.player_position(), radius).into_iter()
+        .filter(|id| game.world.position(*id).is_some_and(|position| game.world.can_see(game.player, position)))
+        .collect();
+    // Items first, so whoever stands on them is drawn on top
+    let (items, creatures): (Vec<EntityId>, Vec<EntityId>) = visible.into_iter().partition(|id| game.world.items.contains(*id));
+    for id in items.into_iter().chain(creatures) {
+        render_entity(surface, camera_position, game, id);
     }
     if game.current_state() == GameState::InspectTiles {
-        render_cursor(rustbox, camera_position)
+        render_cursor(surface, camera_position)
     }
-    render_side_panel(rustbox, game);
-    rustbox.present();
+    render_side_panel(surface, game);
+    surface.present();
 }
 
-fn game_coords_to_camera(rustbox: &RustBox, camera_position: &Position, position: &Position) -> (usize, usize) {
-    let result_x = position.x() + (rustbox.width() as i32) / 2 - camera_position.x();
-    let result_y = position.y() + (rustbox.height() as i32) / 2 - camera_position.y();
+fn game_coords_to_camera(surface: &dyn Surface, camera_position: &Position, position: &Position) -> (usize, usize) {
+    let result_x = position.x() + (surface.width() as i32) / 2 - camera_position.x();
+    let result_y = position.y() + (surface.height() as i32) / 2 - camera_position.y();
     if result_x < 0 || result_y < 0 {
-        return (rustbox.width()+1, rustbox.height()+1)
+        return (surface.width()+1, surface.height()+1)
     }
     (result_x as usize, result_y as usize)
 }
 
-fn is_visible(rustbox: &RustBox, x: usize, y: usize) -> bool {
-    x < rustbox.width() && y < rustbox.height() && x > SIDE_PANEL_WIDTH
+fn is_visible(surface: &dyn Surface, x: usize, y: usize) -> bool {
+    x < surface.width() && y < surface.height() && x > SIDE_PANEL_WIDTH
 }
 
-fn render_map(rustbox: &RustBox, camera_position: &Position, player: &super::Player, map: &super::GameMap) {
-    let portion = map.portion_around(camera_position, 100);
-    for i in 0..portion.len() {
-        let tile = &portion[i];
-        if player.can_see(tile.position()) {
-            render_tile(rustbox, tile, camera_position);
+fn render_map(surface: &mut dyn Surface, camera_position: &Position, game: &Game) {
+    // Only what fits on the surface, which is never more than what's kept generated
+    let distance = min(super::VIEW_RADIUS, max(surface.width(), surface.height()) as i32 / 2 + 1);
+    let map = &game.map;
+    let view = map.view_around(camera_position, distance);
+    for (position, tile) in view.iter() {
+        if game.world.can_see(game.player, &position) {
+            render_tile(surface, &position, tile, camera_position, false);
+        } else if map.is_explored(position.x(), position.y()) {
+            render_tile(surface, &position, tile, camera_position, true);
         }
     }
 }
 
-fn render_tile(rustbox: &RustBox, tile: &super::Tile, camera_position: &Position) {
-    let position = tile.position();
-    let (x, y) = game_coords_to_camera(rustbox, camera_position, position);
-    if is_visible(rustbox, x, y) {
+// Remembered tiles are drawn in bold black, which most terminals show as dark gray.
+fn render_tile(surface: &mut dyn Surface, position: &Position, tile: &super::Tile, camera_position: &Position, remembered: bool) {
+    let (x, y) = game_coords_to_camera(surface, camera_position, position);
+    if is_visible(surface, x, y) {
         let bg_color = Color::Black;
-        let fg_color = match &tile.tile_type {
-            super::TileType::Grass => Color::Green,
-            super::TileType::Dirt => Color::Yellow,
-        };
-        let graphic = match &tile.tile_type {
-            super::TileType::Grass => match &tile.variant {
+        let (style, fg_color) = if remembered { (Style::Bold, Color::Black) } else { (Style::Normal, tile_color(tile.tile_type())) };
+        let graphic = match tile.tile_type() {
+            super::TileType::Grass => match tile.variant() {
                 1 => ",",
                 2 => " ",
                 3 => "'",
                 4 => "\"",
                 _ => " "
             },
-            super::TileType::Dirt => match &tile.variant {
+            super::TileType::Dirt => match tile.variant() {
                 1 => " ",
                 2 => "-",
                 3 => ".",
                 4 => "_",
                 _ => " "
-            }
+            },
+            super::TileType::StoneWall => "#",
+            super::TileType::DeepWater => "~",
+            super::TileType::ShallowWater => "~",
+            super::TileType::Tree => "T",
+            super::TileType::Boulder => "o",
+            super::TileType::Sand => match tile.variant() {
+                1 => ".",
+                2 => "~",
+                _ => " "
+            },
+            super::TileType::Mud => match tile.variant() {
+                1 => ",",
+                2 => ".",
+                _ => " "
+            },
+            super::TileType::Rock => "^",
+            super::TileType::Floor => ".",
+            super::TileType::Door => "+",
+            super::TileType::StairsDown => ">",
+            super::TileType::StairsUp => "<"
         };
-        rustbox.print(x, y, rustbox::RB_NORMAL, fg_color, bg_color, graphic);
+        surface.print(x, y, style, fg_color, bg_color, graphic);
     }
 }
 
-fn render_player(rustbox: &RustBox, camera_position: &Position, player_position: &Position) {
-    let (x, y) = game_coords_to_camera(rustbox, camera_position, player_position);
-    if is_visible(rustbox, x, y) {
-        rustbox.print(x, y, rustbox::RB_NORMAL, Color::White, Color::Default, "@");
+fn tile_color(tile_type: super::TileType) -> Color {
+    match tile_type {
+        super::TileType::Grass | super::TileType::Tree => Color::Green,
+        super::TileType::Dirt => Color::Yellow,
+        super::TileType::StoneWall | super::TileType::Boulder => Color::White,
+        super::TileType::DeepWater => Color::Blue,
+        super::TileType::ShallowWater => Color::Cyan,
+        super::TileType::Sand => Color::Yellow,
+        super::TileType::Mud => Color::Magenta,
+        super::TileType::Rock | super::TileType::Floor => Color::White,
+        super::TileType::StairsDown | super::TileType::StairsUp => Color::White,
+        super::TileType::Door => Color::Yellow
     }
 }
 
-fn render_entity(rustbox: &RustBox, camera_position: &Position, entity: &dyn Entity) {
-    let (x, y) = game_coords_to_camera(rustbox, camera_position, entity.current_position());
-    if is_visible(rustbox, x, y) {
-        rustbox.print(x, y, rustbox::RB_NORMAL, entity.render_info().color, Color::Default, &entity.render_info().character.to_string());
+fn render_entity(surface: &mut dyn Surface, camera_position: &Position, game: &Game, id: EntityId) {
+    let (position, render_info) = match (game.world.position(id), game.world.render_info.get(id)) {
+        (Some(position), Some(render_info)) => (position, render_info),
+        _ => return
+    };
+    let (x, y) = game_coords_to_camera(surface, camera_position, position);
+    if is_visible(surface, x, y) {
+        surface.print(x, y, Style::Normal, render_info.color, Color::Default, &render_info.character.to_string());
     }
 }
 
-fn render_cursor(rustbox: &RustBox, camera_position: &Position) {
-    let (x, y) = game_coords_to_camera(rustbox, camera_position, camera_position);
-    rustbox.print(x, y, rustbox::RB_NORMAL, Color::White, Color::Default, "X");
+fn render_cursor(surface: &mut dyn Surface, camera_position: &Position) {
+    let (x, y) = game_coords_to_camera(surface, camera_position, camera_position);
+    surface.print(x, y, Style::Normal, Color::White, Color::Default, "X");
 }
 
-fn render_side_panel(rustbox: &RustBox, game: &Game) {
-    for i in 0..rustbox.width() {
-        rustbox.print(SIDE_PANEL_WIDTH, i, rustbox::RB_NORMAL, Color::White, Color::Default, "|");
+fn render_side_panel(surface: &mut dyn Surface, game: &Game) {
+    for i in 0..surface.height() {
+        surface.print(SIDE_PANEL_WIDTH, i, Style::Normal, Color::White, Color::Default, "|");
     }
-    let mut y = render_entity_info(rustbox, game, 0);
-    y = y + render_tile_info(rustbox, game, y);
-    if y > 0 { y = y + 1 }
-    render_debug(rustbox, game, y);
+    let mut y = render_entity_info(surface, game, 0);
+    y = y + render_tile_info(surface, game, y);
+    if y > 0 { y += 1 }
+    render_debug(surface, game, y);
 }
 
-fn render_entity_info(rustbox: &RustBox, game: &Game, y: usize) -> usize {
+fn render_entity_info(surface: &mut dyn Surface, game: &Game, y: usize) -> usize {
     let position = game.camera_position();
-    let optional_entity = game.entities_at(position).pop();
+    // Whoever stands there rather than what lies under them
+    let mut ids = game.world.entities_at(position);
+    ids.sort_by_key(|id| !game.world.items.contains(*id));
+    let optional_name = ids.last().and_then(|id| game.world.names.get(*id).map(|name| (*id, name)));
     let mut lines = 0;
-    if optional_entity.is_some() {
-        let entity = optional_entity.unwrap();
-        let name = entity.name();
-        let mut x = 0;
-        if name.is_some() {
-            x = name.unwrap().len() + 1;
-            rustbox.print(0, y + lines, rustbox::RB_BOLD, Color::White, Color::Default, name.unwrap());
-            lines += 1;
-        }
-        if x > 0 {
-            rustbox.print(x, y + lines, rustbox::RB_NORMAL, Color::White, Color::Default, &format!(", {}", entity.kind()));
-        } else {
-            rustbox.print(x, y + lines, rustbox::RB_NORMAL, Color::White, Color::Default, entity.kind());
+    if let Some((id, name)) = optional_name.filter(|_| game.world.can_see(game.player, position)) {
+        match &name.name {
+            Some(proper_name) => {
+                surface.print(0, y + lines, Style::Bold, Color::White, Color::Default, proper_name);
+                surface.print(proper_name.len(), y + lines, Style::Normal, Color::White, Color::Default, &format!(", {}", name.kind));
+            },
+            None => surface.print(0, y + lines, Style::Normal, Color::White, Color::Default, &name.kind)
         }
         lines += 2;
-        lines += render_character_stats(rustbox, entity.stats(), lines);
-    } else if game.player_position() == position {
-        let x = game.player.name().unwrap().len();
-        rustbox.print(0, y + lines, rustbox::RB_BOLD, Color::White, Color::Default, game.player.name().unwrap());
-        rustbox.print(x, y + lines, rustbox::RB_NORMAL, Color::White, Color::Default, &format!(", {}", game.player.kind()));
-        lines += 2;
-        lines += render_character_stats(rustbox, game.player.stats(), lines);
+        if let Some(stats) = game.world.stats.get(id) {
+            lines += render_character_stats(surface, stats, lines);
+        }
     }
     lines + 1
 }
 
-fn render_character_stats(rustbox: &RustBox, stats: &CharacterStats, y: usize) -> usize {
+fn render_character_stats(surface: &mut dyn Surface, stats: &CharacterStats, y: usize) -> usize {
     let mut lines = 0;
     for stat in stats.as_vec() {
         let mut x = 0;
@@ -157,39 +194,137 @@ fn render_character_stats(rustbox: &RustBox, stats: &CharacterStats, y: usize) -
             "Perception" => Color::Blue,
             _ => Color::White
         };
-        rustbox.print(x, y + lines, rustbox::RB_NORMAL, fg_color, Color::Default, name);
-        x = x + name.len();
-        rustbox.print(x, y + lines, rustbox::RB_NORMAL, Color::White, Color::Default, &format!(": {}", stat.lvl()));
-        lines = lines + 1;
+        surface.print(x, y + lines, Style::Normal, fg_color, Color::Default, name);
+        x += name.len();
+        surface.print(x, y + lines, Style::Normal, Color::White, Color::Default, &format!(": {}", stat.lvl()));
+        lines += 1;
     }
     lines
 }
 
-fn render_tile_info(rustbox: &RustBox, game: &Game, y: usize) -> usize {
-    let tile = game.tile_at(game.camera_position());
+fn render_tile_info(surface: &mut dyn Surface, game: &Game, y: usize) -> usize {
     let mut lines = 0;
     let mut x = 0;
-    if tile.is_some() {
-        let fg_color = match tile.unwrap().tile_type {
-            super::TileType::Grass => Color::Green,
-            super::TileType::Dirt => Color::Yellow,
-        };
-        let name = tile.unwrap().name();
-        rustbox.print(x, y + lines, rustbox::RB_NORMAL, fg_color, Color::Default, name);
-        x = x + name.len();
-        let passable = tile.unwrap().is_passable() && game.is_passable(game.camera_position());
+    let position = game.camera_position();
+    if let Some(tile) = game.tile_at(position).filter(|_| game.map.is_explored(position.x(), position.y())) {
+        let fg_color = tile_color(tile.tile_type());
+        let name = tile.name();
+        surface.print(x, y + lines, Style::Normal, fg_color, Color::Default, name);
+        x += name.len();
+        // Entities out of sight don't count, the player can't know about them
+        let passable = tile.is_passable() && (!game.world.can_see(game.player, position) || game.is_passable(position));
         let passability = if passable { "Passable" } else { "Blocked" };
-        rustbox.print(x, y + lines, rustbox::RB_NORMAL, Color::White, Color::Default, &format!(", {}", passability));
-        lines = lines + 1;
+        surface.print(x, y + lines, Style::Normal, Color::White, Color::Default, &format!(", {}", passability));
+        lines += 1;
     }
     lines
 }
 
-fn render_debug(rustbox: &RustBox, game: &Game, y: usize) -> usize {
-    rustbox.print(0, y, rustbox::RB_NORMAL, Color::White, Color::Default, "Debug Info:");
-    rustbox.print(0, y + 1, rustbox::RB_NORMAL, Color::White, Color::Default, &format!("Ticks: {}", game.tick_count()).to_string());
-    rustbox.print(0, y + 2, rustbox::RB_NORMAL, Color::White, Color::Default, &format!("Entity Count: {}", game.entities.len()).to_string());
-    rustbox.print(0, y + 3, rustbox::RB_NORMAL, Color::White, Color::Default, &format!("Player Pos: {} {}", game.player_position().x(), game.player_position().y()).to_string());
-    rustbox.print(0, y + 4, rustbox::RB_NORMAL, Color::White, Color::Default, &format!("Game Mode: {}", game.state.to_string()));
-    y + 5
-}
\ No newline at end of file
+fn render_debug(surface: &mut dyn Surface, game: &Game, y: usize) -> usize {
+    surface.print(0, y, Style::Normal, Color::White, Color::Default, "Debug Info:");
+    surface.print(0, y + 1, Style::Normal, Color::White, Color::Default, &format!("Ticks: {}", game.tick_count()).to_string());
+    surface.print(0, y + 2, Style::Normal, Color::White, Color::Default, &format!("Entity Count: {}", game.world.len()).to_string());
+    surface.print(0, y + 3, Style::Normal, Color::White, Color::Default, &format!("Player Pos: {} {}", game.player_position().x(), game.player_position().y()).to_string());
+    surface.print(0, y + 4, Style::Normal, Color::White, Color::Default, &format!("Game Mode: {}", game.state));
+    surface.print(0, y + 5, Style::Normal, Color::White, Color::Default, &format!("Loaded Chunks: {}", game.map.loaded_chunks()));
+    surface.print(0, y + 6, Style::Normal, Color::White, Color::Default, &format!("Depth: {}", game.depth()));
+    y + 7
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::command::Command;
+    use super::super::config::GameConfig;
+    use super::super::map::{Tile, TileType};
+    use super::super::surface::MemorySurface;
+
+    const WIDTH: usize = 80;
+    const HEIGHT: usize = 24;
+
+    // Where the camera ends up on the surface
+    const CENTER: (usize, usize) = (WIDTH / 2, HEIGHT / 2);
+
+    // A game without animals where the player stands on dirt with a few known tiles around
+    fn game() -> Game {
+        let config = GameConfig { seed: 7, map_size: Some(64), animals: 0, spawn_radius: 5, ..GameConfig::default() };
+        let mut game = Game::new(&config).unwrap();
+        let start = *game.player_position();
+        let tiles = [
+            ((0, 0), TileType::Dirt), ((1, 0), TileType::StoneWall), ((-1, 0), TileType::Floor),
+            ((0, -1), TileType::ShallowWater), ((0, 1), TileType::Door), ((-1, -1), TileType::Tree),
+            ((1, 1), TileType::StairsDown), ((-1, 1), TileType::Rock), ((1, -1), TileType::Boulder)
+        ];
+        for ((dx, dy), tile_type) in tiles.iter() {
+            game.map.replace_tile(start.x() + dx, start.y() + dy, Tile::new(*tile_type, 0));
+        }
+        game.update_surroundings();
+        game
+    }
+
+    fn rendered(game: &Game) -> MemorySurface {
+        let mut surface = MemorySurface::new(WIDTH, HEIGHT);
+        render(&mut surface, game);
+        surface
+    }
+
+    fn glyph(surface: &MemorySurface, dx: i32, dy: i32) -> char {
+        surface.cell((CENTER.0 as i32 + dx) as usize, (CENTER.1 as i32 + dy) as usize).unwrap().character
+    }
+
+    #[test]
+    fn tiles_and_the_player_are_drawn_around_the_camera() {
+        let surface = rendered(&game());
+        assert_eq!(surface.present_count(), 1);
+        assert_eq!(glyph(&surface, 0, 0), '@');
+        assert_eq!(surface.cell(CENTER.0, CENTER.1).unwrap().fg, Color::White);
+        let expected = [
+            ((1, 0), '#'), ((-1, 0), '.'), ((0, -1), '~'), ((0, 1), '+'),
+            ((-1, -1), 'T'), ((1, 1), '>'), ((-1, 1), '^'), ((1, -1), 'o')
+        ];
+        for ((dx, dy), character) in expected.iter() {
+            assert_eq!(glyph(&surface, *dx, *dy), *character, "tile at {}, {}", dx, dy);
+        }
+        assert_eq!(surface.cell(CENTER.0, CENTER.1 - 1).unwrap().fg, Color::Cyan);
+        // The map stops where the side panel starts
+        assert!((0..HEIGHT).all(|y| surface.cell(SIDE_PANEL_WIDTH, y).unwrap().character == '|'));
+    }
+
+    #[test]
+    fn the_side_panel_describes_the_player_and_the_game() {
+        let game = game();
+        let surface = rendered(&game);
+        let panel: Vec<String> = (0..HEIGHT).map(|y| surface.row(y)[..SIDE_PANEL_WIDTH].trim_end().to_string()).collect();
+        let position = game.player_position();
+        assert_eq!(panel[0], "Player, Human");
+        assert_eq!(&panel[2..5], ["Strength: 5", "Dexterity: 5", "Perception: 30"]);
+        // The player stands in the way of its own tile
+        assert_eq!(panel[6], "Dirt, Blocked");
+        assert_eq!(&panel[8..15], [
+            "Debug Info:".to_string(),
+            "Ticks: 0".to_string(),
+            format!("Entity Count: {}", game.world.len()),
+            format!("Player Pos: {} {}", position.x(), position.y()),
+            "Game Mode: Gameplay".to_string(),
+            format!("Loaded Chunks: {}", game.map().loaded_chunks()),
+            "Depth: 0".to_string()
+        ]);
+    }
+
+    #[test]
+    fn inspecting_moves_the_camera_to_the_cursor() {
+        let mut game = game();
+        game.step(Command::ToggleInspect);
+        game.step(Command::Move(1, 0));
+        let surface = rendered(&game);
+        // The cursor is drawn in the middle over the wall, the player one tile to the left
+        assert_eq!(glyph(&surface, 0, 0), 'X');
+        assert_eq!(glyph(&surface, -1, 0), '@');
+        assert_eq!(glyph(&surface, -2, 0), '.');
+        assert_eq!(glyph(&surface, 0, 1), '>');
+        let panel: Vec<String> = (0..HEIGHT).map(|y| surface.row(y)[..SIDE_PANEL_WIDTH].trim_end().to_string()).collect();
+        // Nobody stands on the wall, so only the tile is described
+        assert_eq!(panel[0], "");
+        assert_eq!(panel[1], "Stone Wall, Blocked");
+        assert!(panel.contains(&"Game Mode: InspectTiles".to_string()));
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use super::Game;
+use super::command::Command;
+use super::config::GameConfig;
+
+pub const REPLAY_VERSION: u32 = 3;
+
+/// A command and the tick it was given at.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayEvent {
+    /// [`Game::tick_count`] when the command was given.
+    pub tick: usize,
+    /// What the player asked for.
+    pub command: Command
+}
+
+/// Commands of a recorded session. A session is fully determined by its config (which holds
+/// the seed) and the commands fed to it, so this is enough to reproduce it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay {
+    version: u32,
+    config: GameConfig,
+    events: Vec<ReplayEvent>,
+    final_hash: Option<u64>
+}
+
+/// Why a replay couldn't be written, read back or played.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The file couldn't be written or read.
+    Io(io::Error),
+    /// The file doesn't hold a valid replay, for the given reason.
+    Corrupt(String),
+    /// The file has a version of the format this game doesn't read.
+    UnsupportedVersion(u32),
+    /// The game didn't reach the tick an event was recorded at.
+    Desync {
+        /// Index of the event in [`Replay::events`].
+        event: usize,
+        /// Tick the event was recorded at.
+        expected_tick: usize,
+        /// Tick the game is at.
+        tick: usize
+    },
+    /// The game played to the end but ended up in another state than when recorded.
+    HashMismatch {
+        /// Hash recorded at the end of the session.
+        expected: u64,
+        /// Hash of the state the replay led to.
+        found: u64
+    },
+    /// Playing was stopped before the end.
+    Interrupted
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "could not access replay file: {}", e),
+            ReplayError::Corrupt(reason) => write!(f, "replay file is corrupt: {}", reason),
+            ReplayError::UnsupportedVersion(version) => write!(f, "unsupported replay version {}", version),
+            ReplayError::Desync { event, expected_tick, tick } => {
+                write!(f, "replay diverged at event {}: recorded at tick {} but the game is at tick {}", event, expected_tick, tick)
+            },
+            ReplayError::HashMismatch { expected, found } => {
+                write!(f, "final state hash {:016x} does not match the recorded {:016x}", found, expected)
+            },
+            ReplayError::Interrupted => write!(f, "replay interrupted")
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ReplayError {
+    fn from(e: serde_json::Error) -> Self {
+        ReplayError::Corrupt(e.to_string())
+    }
+}
+
+impl Replay {
+    /// An empty recording of a game created from `config`.
+    pub fn new(config: &GameConfig) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            config: config.clone(),
+            events: Vec::new(),
+            final_hash: None
+        }
+    }
+
+    /// Reads a replay written by [`Replay::save`].
+    pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+        let replay: Replay = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.version))
+        }
+        Ok(replay)
+    }
+
+    /// Writes the replay to `path`, replacing any file there.
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// What the recorded game was created from.
+    pub fn config(&self) -> &GameConfig {
+        &self.config
+    }
+
+    /// Recorded commands, in order.
+    pub fn events(&self) -> &[ReplayEvent] {
+        &self.events
+    }
+
+    /// [`Game::state_hash`] at the end of the session, if the recording was finished.
+    pub fn final_hash(&self) -> Option<u64> {
+        self.final_hash
+    }
+
+    /// Adds `command`, given at `tick`.
+    pub fn record(&mut self, tick: usize, command: Command) {
+        self.events.push(ReplayEvent { tick, command });
+    }
+
+    /// Seals the replay with the state it should lead to.
+    pub fn set_final_hash(&mut self, hash: u64) {
+        self.final_hash = Some(hash);
+    }
+
+    /// Creates the game the replay was recorded from. Fails if the recorded config is invalid.
+    pub fn start(&self) -> Result<Game, ReplayError> {
+        Game::new(&self.config).map_err(|e| ReplayError::Corrupt(e.to_string()))
+    }
+
+    /// Feeds the recorded commands to a game created by [`Replay::start`], then checks the final
+    /// state and returns its hash. `before_step` is called ahead of every command and can stop the
+    /// replay by returning false.
+    pub fn play<F: FnMut(&Game) -> bool>(&self, game: &mut Game, mut before_step: F) -> Result<u64, ReplayError> {
+        for (i, event) in self.events.iter().enumerate() {
+            if !before_step(game) {
+                return Err(ReplayError::Interrupted)
+            }
+            if game.tick_count() != event.tick {
+                return Err(ReplayError::Desync { event: i, expected_tick: event.tick, tick: game.tick_count() })
+            }
+            game.step(event.command);
+        }
+        let hash = game.state_hash();
+        match self.final_hash {
+            Some(expected) if expected != hash => Err(ReplayError::HashMismatch { expected, found: hash }),
+            _ => Ok(hash)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GameConfig {
+        GameConfig { seed: 99, map_size: Some(64), animals: 5, spawn_radius: 10, ..GameConfig::default() }
+    }
+
+    // Plays `commands` on a new game, recording them the way the frontend does
+    fn record(commands: &[Command]) -> (Replay, u64) {
+        let mut replay = Replay::new(&config());
+        let mut game = replay.start().unwrap();
+        for command in commands {
+            replay.record(game.tick_count(), *command);
+            game.step(*command);
+        }
+        let hash = game.state_hash();
+        replay.set_final_hash(hash);
+        (replay, hash)
+    }
+
+    fn session() -> Vec<Command> {
+        let mut commands = vec![Command::Move(1, 0); 4];
+        commands.extend(vec![Command::Wait; 3]);
+        commands.extend(vec![Command::Move(0, 1); 4]);
+        commands
+    }
+
+    fn play(replay: &Replay) -> Result<u64, ReplayError> {
+        let mut game = replay.start()?;
+        replay.play(&mut game, |_| true)
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rust-game-replay-{}-{}.json", std::process::id(), name))
+    }
+
+    #[test]
+    fn recorded_session_replays_to_the_same_state() {
+        let (replay, hash) = record(&session());
+        assert_eq!(play(&replay).unwrap(), hash);
+
+        let path = temp_path("round-trip");
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.events().len(), session().len());
+        assert_eq!(loaded.final_hash(), Some(hash));
+        assert_eq!(play(&loaded).unwrap(), hash);
+    }
+
+    #[test]
+    fn modified_commands_lead_to_another_state() {
+        let (mut replay, hash) = record(&session());
+        // Same ticks, other way
+        for event in replay.events.iter_mut().filter(|event| event.command == Command::Move(1, 0)) {
+            event.command = Command::Move(-1, 0);
+        }
+        match play(&replay) {
+            Err(ReplayError::HashMismatch { expected, .. }) => assert_eq!(expected, hash),
+            other => panic!("expected a hash mismatch, got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn commands_at_other_ticks_desync() {
+        let (mut replay, _) = record(&session());
+        replay.events[2].tick += 1;
+        match play(&replay) {
+            Err(ReplayError::Desync { event: 2, expected_tick: 3, tick: 2 }) => (),
+            other => panic!("expected a desync, got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn replays_without_a_final_hash_play_back() {
+        // What's left when the game is killed before the session ends
+        let (mut replay, hash) = record(&session());
+        replay.final_hash = None;
+        assert_eq!(play(&replay).unwrap(), hash);
+    }
+
+    #[test]
+    fn stopping_playback_interrupts() {
+        let (replay, _) = record(&session());
+        let mut game = replay.start().unwrap();
+        let result = replay.play(&mut game, |game| game.tick_count() < 5);
+        assert!(matches!(result, Err(ReplayError::Interrupted)));
+        assert_eq!(game.tick_count(), 5);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let (replay, _) = record(&session());
+        for version in [0, REPLAY_VERSION - 1, REPLAY_VERSION + 1].iter() {
+            let path = temp_path(&format!("version-{}", version));
+            let mut old = replay.clone();
+            old.version = *version;
+            old.save(&path).unwrap();
+            let result = Replay::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            match result {
+                Err(ReplayError::UnsupportedVersion(found)) => assert_eq!(found, *version),
+                other => panic!("expected version {} to be rejected, got {:?}", version, other.map(|_| ()))
+            }
+        }
+    }
+}
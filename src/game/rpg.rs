@@ -1,11 +1,18 @@
-#[derive(Clone)]
+use serde::{Serialize, Deserialize};
+
+/// Strength, dexterity and perception of a character.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CharacterStats {
+  /// Strength.
   pub str: CharacterStat,
+  /// Dexterity, how fast the character acts.
   pub dex: CharacterStat,
+  /// Perception, how far the character sees.
   pub per: CharacterStat
 }
 
 impl CharacterStats {
+  /// Stats starting at the given levels, without experience.
   pub fn new(str: usize, dex: usize, per: usize) -> CharacterStats {
     CharacterStats {
       str: CharacterStat::new("Strength", str),
@@ -14,12 +21,19 @@ impl CharacterStats {
     }
   }
 
+  /// Energy gathered every turn, so twice the dexterity acts twice as often.
+  pub fn speed(&self) -> usize {
+    self.dex.lvl().max(1)
+  }
+
+  /// Every stat, in the order they are shown.
   pub fn as_vec(&self) -> Vec<&CharacterStat> {
     vec![&self.str, &self.dex, &self.per]
   }
 }
 
-#[derive(Clone)]
+/// A stat that levels up as it gains experience.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CharacterStat {
   name: String,
   level: usize,
@@ -27,6 +41,7 @@ pub struct CharacterStat {
 }
 
 impl CharacterStat {
+  /// A stat called `name` at `level`, without experience.
   pub fn new(name: &str, level: usize) -> CharacterStat {
     CharacterStat {
       name: name.to_string(),
@@ -35,22 +50,27 @@ impl CharacterStat {
     }
   }
 
+  /// What the stat is called, like "Strength".
   pub fn name(&self) -> &str {
     &self.name
   }
 
+  /// Current level.
   pub fn lvl(&self) -> usize {
     self.level
   }
 
+  /// Experience gathered towards the next level.
   pub fn current_exp(&self) -> usize {
     self.exp
   }
 
+  /// Experience it takes to go past the current level.
   pub fn exp_for_next_level(&self) -> usize {
     self.level * 10
   }
 
+  /// Adds experience, leveling up once there is more than the next level needs.
   pub fn gain_exp(&mut self, amount: usize) {
     self.exp += amount;
     self.check_level_up()
@@ -59,8 +79,8 @@ impl CharacterStat {
   fn check_level_up(&mut self) {
     let required_exp = self.exp_for_next_level();
     if self.exp > required_exp {
-      self.level = self.level + 1;
-      self.exp = self.exp - required_exp;
+      self.level += 1;
+      self.exp -= required_exp;
     }
   }
 }
\ No newline at end of file
@@ -0,0 +1,620 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use super::{Game, GameState, GameRng};
+use super::config::GameConfig;
+use super::level::{Level, Stairs};
+use super::map::{GameMap, CHUNK_SIZE};
+use super::position::Position;
+use super::registry::{EntityId, SlotState};
+use super::world::{EntityBuilder, World};
+
+// Each migration upgrades a save from the version matching its position in the list
+// (starting at 1) to the next one, so the current version is one past the last migration.
+type Migration = fn(Value) -> Result<Value, SaveError>;
+
+const MIGRATIONS: &[Migration] = &[
+    split_map_into_chunks,
+    add_explored_tiles,
+    add_levels,
+    add_entity_generations,
+    compose_entities,
+    add_energy,
+    reserve_player_slots
+];
+
+pub const SAVE_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+// A slot of the entity registry of a world, free or not, so entity IDs stay the same once loaded
+#[derive(Serialize, Deserialize)]
+struct SavedSlot {
+    generation: u32,
+    // Kept for the player while it's on another level
+    #[serde(default, skip_serializing_if = "is_false")]
+    reserved: bool,
+    entity: Option<EntityBuilder>
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn save_entities(world: &World) -> Vec<SavedSlot> {
+    world.slots().into_iter().map(|(generation, state, entity)| {
+        SavedSlot { generation, reserved: state == SlotState::Reserved, entity }
+    }).collect()
+}
+
+fn load_entities(slots: Vec<SavedSlot>) -> World {
+    World::from_slots(slots.into_iter().map(|slot| {
+        let state = match (slot.reserved, &slot.entity) {
+            (true, _) => SlotState::Reserved,
+            (false, Some(_)) => SlotState::Alive,
+            (false, None) => SlotState::Free
+        };
+        (slot.generation, state, slot.entity)
+    }).collect())
+}
+
+/// Why a save file couldn't be written or read back.
+#[derive(Debug)]
+pub enum SaveError {
+    /// The file couldn't be written or read.
+    Io(io::Error),
+    /// The file doesn't hold a valid save, for the given reason.
+    Corrupt(String),
+    /// The file was written by a newer version of the game.
+    NewerVersion {
+        /// Version of the save file.
+        found: u32,
+        /// Latest version this game reads.
+        supported: u32
+    }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "could not access save file: {}", e),
+            SaveError::Corrupt(reason) => write!(f, "save file is corrupt: {}", reason),
+            SaveError::NewerVersion { found, supported } => {
+                write!(f, "save file has version {} but this game only supports up to version {}", found, supported)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self {
+        // Writing or reading the file itself can fail halfway through serializing
+        if e.is_io() {
+            SaveError::Io(e.into())
+        } else {
+            SaveError::Corrupt(e.to_string())
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SavedLevelRef<'a> {
+    depth: usize,
+    map: &'a GameMap,
+    entities: Vec<SavedSlot>,
+    stairs: &'a Stairs
+}
+
+#[derive(Deserialize)]
+struct SavedLevel {
+    depth: usize,
+    map: GameMap,
+    entities: Vec<SavedSlot>,
+    stairs: Stairs
+}
+
+#[derive(Serialize)]
+struct SaveFileRef<'a> {
+    version: u32,
+    config: &'a GameConfig,
+    map: &'a GameMap,
+    player: EntityId,
+    entities: Vec<SavedSlot>,
+    depth: usize,
+    stairs: &'a Stairs,
+    levels: Vec<SavedLevelRef<'a>>,
+    rng: &'a GameRng,
+    tick_count: usize
+}
+
+#[derive(Deserialize)]
+struct SaveFile {
+    config: GameConfig,
+    map: GameMap,
+    player: EntityId,
+    entities: Vec<SavedSlot>,
+    depth: usize,
+    stairs: Stairs,
+    levels: Vec<SavedLevel>,
+    rng: GameRng,
+    tick_count: usize
+}
+
+impl Game {
+    fn save_file(&self) -> SaveFileRef<'_> {
+        SaveFileRef {
+            version: SAVE_VERSION,
+            config: &self.config,
+            map: &self.map,
+            player: self.player,
+            entities: save_entities(&self.world),
+            depth: self.depth,
+            stairs: &self.stairs,
+            levels: self.levels.iter().map(|(depth, level)| SavedLevelRef {
+                depth: *depth,
+                map: &level.map,
+                entities: save_entities(&level.world),
+                stairs: &level.stairs
+            }).collect(),
+            rng: &self.rng,
+            tick_count: self.tick_count
+        }
+    }
+
+    /// Writes the full game state to `path`, replacing any previous save.
+    pub fn save(&self, path: &Path) -> Result<(), SaveError> {
+        let save = self.save_file();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write next to the old save first so a failure can't leave a truncated file behind.
+        let temp_path = path.with_extension("tmp");
+        let written = File::create(&temp_path).map_err(SaveError::from).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &save)?;
+            writer.flush()?;
+            drop(writer);
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        });
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written
+    }
+
+    /// Reads a save written by [`Game::save`], upgrading it if it comes from an older version.
+    pub fn load(path: &Path) -> Result<Game, SaveError> {
+        let value: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let save: SaveFile = serde_json::from_value(migrate(value)?)?;
+        // Deeper levels are generated from it later on
+        save.config.validate().map_err(|e| SaveError::Corrupt(e.to_string()))?;
+        let levels = save.levels.into_iter().map(|level| (level.depth, Level {
+            map: level.map,
+            world: load_entities(level.entities),
+            stairs: level.stairs
+        })).collect();
+        let mut game = Game {
+            config: save.config,
+            state: GameState::Gameplay,
+            player: save.player,
+            cursor: Position::default(),
+            world: load_entities(save.entities),
+            map: save.map,
+            depth: save.depth,
+            stairs: save.stairs,
+            levels,
+            next_depth: None,
+            rng: save.rng,
+            tick_count: save.tick_count,
+            last_command: None,
+            recording: None
+        };
+        if !game.world.players.contains(game.player) || game.world.position(game.player).is_none() {
+            return Err(SaveError::Corrupt("missing player".to_string()))
+        }
+        if let Some(depth) = game.levels.iter().find(|(_, level)| !level.world.is_reserved(game.player)).map(|(depth, _)| depth) {
+            return Err(SaveError::Corrupt(format!("no room for the player on level {}", depth)))
+        }
+        game.update_surroundings();
+        if game.depth == 0 && game.stairs.down.is_none() {
+            // Saves from before there were levels
+            let near = *game.player_position();
+            game.add_stairs_down(&near);
+        }
+        Ok(game)
+    }
+
+    /// FNV-1a hash of the serialized state, stable across runs and platforms.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher(0xcbf2_9ce4_8422_2325);
+        serde_json::to_writer(&mut hasher, &self.save_file()).expect("game state is always serializable");
+        hasher.0
+    }
+}
+
+struct StateHasher(u64);
+
+impl Write for StateHasher {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn migrate(mut value: Value) -> Result<Value, SaveError> {
+    let version = match value.get("version").and_then(Value::as_u64) {
+        Some(version) if version >= 1 => version as u32,
+        _ => return Err(SaveError::Corrupt("missing or invalid version".to_string()))
+    };
+    if version > SAVE_VERSION {
+        return Err(SaveError::NewerVersion { found: version, supported: SAVE_VERSION })
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        value = migration(value)?;
+    }
+    value["version"] = Value::from(SAVE_VERSION);
+    Ok(value)
+}
+
+// Version 1 stored the map as one flat square of tiles. Every chunk of it is kept as modified,
+// so the seed the chunks would be regenerated from never comes into play.
+fn split_map_into_chunks(mut value: Value) -> Result<Value, SaveError> {
+    let corrupt = || SaveError::Corrupt("invalid map".to_string());
+    let size = value["map"]["size"].as_u64().ok_or_else(corrupt)? as usize;
+    let tiles = value["map"]["tiles"].as_str().ok_or_else(corrupt)?;
+    if size.checked_mul(size).and_then(|count| count.checked_mul(2)) != Some(tiles.len()) {
+        return Err(corrupt())
+    }
+    let chunk_size = CHUNK_SIZE as usize;
+    let chunk_count = size.div_ceil(chunk_size);
+    let mut chunks = Vec::new();
+    for chunk_x in 0..chunk_count {
+        for chunk_y in 0..chunk_count {
+            let mut chunk_tiles = String::with_capacity(chunk_size * chunk_size * 2);
+            for x in chunk_x * chunk_size..(chunk_x + 1) * chunk_size {
+                for y in chunk_y * chunk_size..(chunk_y + 1) * chunk_size {
+                    // Tiles past the edge of the old map can't be reached, any type will do
+                    let index = x * size + y;
+                    let tile = if x < size && y < size { tiles.get(index * 2..index * 2 + 2).ok_or_else(corrupt)? } else { "00" };
+                    chunk_tiles.push_str(tile);
+                }
+            }
+            chunks.push(json!({ "x": chunk_x, "y": chunk_y, "tiles": chunk_tiles }));
+        }
+    }
+    value["map"] = json!({ "seed": 0, "size": size, "chunks": chunks });
+    Ok(value)
+}
+
+// Version 2 didn't remember which tiles the player had seen.
+fn add_explored_tiles(mut value: Value) -> Result<Value, SaveError> {
+    match value.get_mut("map").and_then(Value::as_object_mut) {
+        Some(map) => {
+            map.insert("explored".to_string(), json!([]));
+            Ok(value)
+        },
+        None => Err(SaveError::Corrupt("missing map".to_string()))
+    }
+}
+
+// Version 3 only had the overworld. The config it was created with wasn't saved, so levels
+// below are generated from the default one. Stairs down are added when the game is loaded.
+fn add_levels(mut value: Value) -> Result<Value, SaveError> {
+    match value.as_object_mut() {
+        Some(save) => {
+            save.insert("config".to_string(), serde_json::to_value(GameConfig::default())?);
+            save.insert("depth".to_string(), json!(0));
+            save.insert("stairs".to_string(), json!({ "up": null, "down": null }));
+            save.insert("levels".to_string(), json!([]));
+            Ok(value)
+        },
+        None => Err(SaveError::Corrupt("not an object".to_string()))
+    }
+}
+
+// Version 4 listed entities without IDs. They get the slots matching their order.
+fn add_entity_generations(mut value: Value) -> Result<Value, SaveError> {
+    let corrupt = || SaveError::Corrupt("invalid entities".to_string());
+    let into_slots = |entities: Option<&mut Value>| -> Result<(), SaveError> {
+        let entities = entities.ok_or_else(corrupt)?;
+        let slots: Vec<Value> = entities.as_array().ok_or_else(corrupt)?.iter()
+            .map(|entity| json!({ "generation": 0, "entity": entity }))
+            .collect();
+        *entities = Value::from(slots);
+        Ok(())
+    };
+    into_slots(value.get_mut("entities"))?;
+    for level in value.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(corrupt)? {
+        into_slots(level.get_mut("entities"))?;
+    }
+    Ok(value)
+}
+
+// Version 5 saved animals and the player as a whole, now they are entities made of components.
+// The player joins the others, in a new slot.
+fn compose_entities(mut value: Value) -> Result<Value, SaveError> {
+    let corrupt = || SaveError::Corrupt("invalid entities".to_string());
+    let into_components = |slots: Option<&mut Value>| -> Result<(), SaveError> {
+        for slot in slots.and_then(Value::as_array_mut).ok_or_else(corrupt)? {
+            let entity = slot.get_mut("entity").ok_or_else(corrupt)?;
+            if entity.is_null() {
+                continue
+            }
+            let animal = entity.get("Animal").ok_or_else(corrupt)?;
+            *entity = json!({
+                "position": animal["position"],
+                "render_info": animal["render_info"],
+                "name": { "name": null, "kind": "Animal" },
+                "stats": animal["character_stats"],
+                "ai": { "Animal": animal["state"] },
+                "vision": {}
+            });
+        }
+        Ok(())
+    };
+    into_components(value.get_mut("entities"))?;
+    for level in value.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(corrupt)? {
+        into_components(level.get_mut("entities"))?;
+    }
+    let player = value.get("player").ok_or_else(corrupt)?;
+    let player = json!({
+        "generation": 0,
+        "entity": {
+            "position": player["position"],
+            "render_info": player["render_info"],
+            "name": { "name": "Player", "kind": "Human" },
+            "stats": player["character_stats"],
+            "vision": {},
+            "inventory": { "items": [] },
+            "player": {}
+        }
+    });
+    let entities = value.get_mut("entities").and_then(Value::as_array_mut).ok_or_else(corrupt)?;
+    entities.push(player);
+    value["player"] = json!({ "index": entities.len() - 1, "generation": 0 });
+    Ok(value)
+}
+
+// Version 6 had every creature act once per turn. They start out with no energy gathered.
+fn add_energy(mut value: Value) -> Result<Value, SaveError> {
+    let corrupt = || SaveError::Corrupt("invalid entities".to_string());
+    let add = |slots: Option<&mut Value>| -> Result<(), SaveError> {
+        for slot in slots.and_then(Value::as_array_mut).ok_or_else(corrupt)? {
+            let entity = slot.get_mut("entity").ok_or_else(corrupt)?;
+            if entity.get("ai").is_some() || entity.get("player").is_some() {
+                entity["energy"] = json!(0);
+            }
+        }
+        Ok(())
+    };
+    add(value.get_mut("entities"))?;
+    for level in value.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(corrupt)? {
+        add(level.get_mut("entities"))?;
+    }
+    Ok(value)
+}
+
+// Version 7 gave the player a new ID on every level it went to. It keeps the one it has now,
+// with its slot set aside on the other levels, and whoever had that slot gets a new one.
+fn reserve_player_slots(mut value: Value) -> Result<Value, SaveError> {
+    let corrupt = || SaveError::Corrupt("invalid entities".to_string());
+    let player = value.get("player").cloned().ok_or_else(corrupt)?;
+    let index = player.get("index").and_then(Value::as_u64).ok_or_else(corrupt)? as usize;
+    // The player is on the current level, so other levels never need more slots than it has
+    if index >= value.get("entities").and_then(Value::as_array).map_or(0, Vec::len) {
+        return Err(corrupt())
+    }
+    for level in value.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(corrupt)? {
+        let slots = level.get_mut("entities").and_then(Value::as_array_mut).ok_or_else(corrupt)?;
+        while slots.len() <= index {
+            slots.push(json!({ "generation": 0, "entity": null }));
+        }
+        let displaced = slots[index].get_mut("entity").map(Value::take).unwrap_or(Value::Null);
+        if !displaced.is_null() {
+            slots.push(json!({ "generation": 0, "entity": displaced }));
+        }
+        slots[index] = json!({ "generation": player["generation"], "reserved": true, "entity": null });
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::command::Command;
+
+    // A save written by the game at an older version, right after a few moves, and what
+    // should come back out of it
+    struct Fixture {
+        version: u32,
+        json: &'static str,
+        position: (i32, i32),
+        depth: usize,
+        // Once loaded, with the animals of prefabs in the chunks generated since
+        entities: usize
+    }
+
+    // The ones at depth 1 went down the stairs first, so they have a level put away
+    const FIXTURES: &[Fixture] = &[
+        Fixture { version: 1, json: include_str!("save_fixtures/v1.json"), position: (13, 12), depth: 0, entities: 4 },
+        Fixture { version: 2, json: include_str!("save_fixtures/v2.json"), position: (34, 32), depth: 0, entities: 4 },
+        Fixture { version: 3, json: include_str!("save_fixtures/v3.json"), position: (34, 32), depth: 0, entities: 4 },
+        Fixture { version: 4, json: include_str!("save_fixtures/v4.json"), position: (51, 49), depth: 1, entities: 1 },
+        Fixture { version: 5, json: include_str!("save_fixtures/v5.json"), position: (34, 32), depth: 0, entities: 4 },
+        Fixture { version: 6, json: include_str!("save_fixtures/v6.json"), position: (34, 32), depth: 0, entities: 4 },
+        Fixture { version: 7, json: include_str!("save_fixtures/v7.json"), position: (51, 49), depth: 1, entities: 1 }
+    ];
+
+    // Tests run side by side, so each one gets its own file
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rust-game-{}-{}.json", std::process::id(), name))
+    }
+
+    fn load_json(name: &str, json: &str) -> Result<Game, SaveError> {
+        let path = temp_path(name);
+        fs::write(&path, json).unwrap();
+        let game = Game::load(&path);
+        fs::remove_file(&path).unwrap();
+        game
+    }
+
+    fn fixture(version: u32) -> Value {
+        let fixture = FIXTURES.iter().find(|fixture| fixture.version == version).unwrap();
+        serde_json::from_str(fixture.json).unwrap()
+    }
+
+    fn current_save(name: &str) -> Value {
+        let game = load_json(name, &fixture(7).to_string()).unwrap();
+        serde_json::to_value(game.save_file()).unwrap()
+    }
+
+    fn assert_corrupt(result: Result<Game, SaveError>) {
+        assert!(matches!(result, Err(SaveError::Corrupt(_))), "expected a corrupt save");
+    }
+
+    #[test]
+    fn every_older_version_migrates_to_the_current_one() {
+        for Fixture { version, json, .. } in FIXTURES {
+            let migrated = migrate(serde_json::from_str(json).unwrap()).unwrap();
+            assert_eq!(migrated["version"], SAVE_VERSION, "from version {}", version);
+            assert!(serde_json::from_value::<SaveFile>(migrated).is_ok(), "from version {}", version);
+        }
+    }
+
+    #[test]
+    fn older_saves_load_where_they_left_off() {
+        for Fixture { version, json, position: (x, y), depth, entities } in FIXTURES {
+            let mut game = load_json(&format!("v{}", version), json).unwrap();
+            assert_eq!(*game.player_position(), Position::new(*x, *y), "from version {}", version);
+            assert_eq!(game.depth(), *depth, "from version {}", version);
+            assert_eq!(game.world().len(), *entities, "from version {}", version);
+            // Older saves have no stairs, they get some like a new game
+            assert!(game.stairs().down.is_some(), "from version {}", version);
+            for level in game.levels.values() {
+                assert!(level.world.is_reserved(game.player()), "from version {}", version);
+            }
+            game.step(Command::Wait);
+        }
+    }
+
+    #[test]
+    fn levels_keep_the_player_id() {
+        let mut game = load_json("levels", &fixture(7).to_string()).unwrap();
+        let player = game.player();
+        let up = game.stairs().up.unwrap();
+        assert_eq!(*game.player_position(), up);
+        game.step(Command::Ascend);
+        assert_eq!(game.depth(), 0);
+        assert_eq!(game.player(), player);
+        assert!(game.world().players.contains(player));
+        assert!(game.levels[&1].world.is_reserved(player));
+    }
+
+    #[test]
+    fn saves_load_back_to_the_same_state() {
+        let game = load_json("round-trip", &fixture(7).to_string()).unwrap();
+        let path = temp_path("round-trip-saved");
+        game.save(&path).unwrap();
+        let loaded = Game::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.state_hash(), game.state_hash());
+    }
+
+    #[test]
+    fn missing_or_newer_versions_are_rejected() {
+        let mut save = fixture(7);
+        save.as_object_mut().unwrap().remove("version");
+        assert_corrupt(load_json("no-version", &save.to_string()));
+        save["version"] = Value::from(0);
+        assert_corrupt(load_json("version-0", &save.to_string()));
+        save["version"] = Value::from(SAVE_VERSION + 1);
+        match load_json("newer", &save.to_string()) {
+            Err(SaveError::NewerVersion { found, supported }) => assert_eq!((found, supported), (SAVE_VERSION + 1, SAVE_VERSION)),
+            _ => panic!("expected a newer version")
+        }
+    }
+
+    #[test]
+    fn damaged_files_are_corrupt() {
+        assert_corrupt(load_json("not-json", "{\"version\": 8, \"map\""));
+        assert_corrupt(load_json("not-an-object", "[8]"));
+        assert!(matches!(Game::load(&temp_path("missing")), Err(SaveError::Io(_))));
+
+        let mut save = fixture(1);
+        let tiles = save["map"]["tiles"].as_str().unwrap().to_string();
+        save["map"]["tiles"] = Value::from(&tiles[..tiles.len() - 2]);
+        assert_corrupt(load_json("short-v1-map", &save.to_string()));
+        save["map"]["tiles"] = Value::from(format!("é{}", &tiles[2..]));
+        assert_corrupt(load_json("non-ascii-v1-map", &save.to_string()));
+
+        let mut save = current_save("current-explored");
+        let explored = save["map"]["explored"][0]["tiles"].as_str().unwrap().to_string();
+        save["map"]["explored"][0]["tiles"] = Value::from(format!("é{}", &explored[2..]));
+        assert_corrupt(load_json("non-ascii-explored", &save.to_string()));
+    }
+
+    #[test]
+    fn saves_without_a_player_are_corrupt() {
+        let mut save = current_save("current-player");
+        save["entities"][0]["entity"].as_object_mut().unwrap().remove("player");
+        assert_corrupt(load_json("no-player", &save.to_string()));
+
+        let mut save = current_save("current-reserved");
+        save["levels"][0]["entities"][0] = json!({ "generation": 0, "entity": null });
+        assert_corrupt(load_json("no-reserved-slot", &save.to_string()));
+
+        let mut save = fixture(7);
+        save["player"] = json!({ "index": 5, "generation": 0 });
+        assert_corrupt(load_json("v7-unknown-player", &save.to_string()));
+        // Would take gigabytes of slots to reserve
+        save["player"] = json!({ "index": u32::MAX, "generation": 0 });
+        assert_corrupt(load_json("v7-huge-player", &save.to_string()));
+    }
+
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no space left on device"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failing_writes_are_io_errors() {
+        let game = load_json("full-disk", &fixture(7).to_string()).unwrap();
+        let e = serde_json::to_writer(FullDisk, &game.save_file()).unwrap_err();
+        assert!(matches!(SaveError::from(e), SaveError::Io(_)));
+        let e = serde_json::from_str::<Value>("{").unwrap_err();
+        assert!(matches!(SaveError::from(e), SaveError::Corrupt(_)));
+    }
+
+    #[test]
+    fn failed_saves_leave_no_temporary_file() {
+        let game = load_json("blocked", &fixture(7).to_string()).unwrap();
+        // A directory can't be replaced by the save
+        let path = temp_path("blocked-dir");
+        fs::create_dir_all(&path).unwrap();
+        assert!(matches!(game.save(&path), Err(SaveError::Io(_))));
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_dir(&path).unwrap();
+    }
+}
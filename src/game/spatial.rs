@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use super::map::ChunkHasher;
+use super::position::Position;
+use super::registry::EntityId;
+
+// Side of the square buckets entities are sorted into. Small enough that looking up a tile
+// only goes through a handful of entries, big enough that range queries touch few buckets.
+const BUCKET_SIZE: i32 = 8;
+
+type Bucket = Vec<(EntityId, Position)>;
+
+fn bucket_of(position: &Position) -> (i32, i32) {
+    (position.x().div_euclid(BUCKET_SIZE), position.y().div_euclid(BUCKET_SIZE))
+}
+
+/// Where entities stand, sorted into a grid of buckets so finding who is on a tile or near a
+/// position doesn't go through every entity. It doesn't know where an entity is on its own,
+/// so it's told where it was when it moves or leaves.
+#[derive(Default)]
+pub struct SpatialIndex {
+    buckets: HashMap<(i32, i32), Bucket, BuildHasherDefault<ChunkHasher>>
+}
+
+impl SpatialIndex {
+    /// Adds entity `id`, which isn't indexed yet, at `position`.
+    pub fn insert(&mut self, id: EntityId, position: &Position) {
+        self.buckets.entry(bucket_of(position)).or_default().push((id, *position));
+    }
+
+    /// Removes entity `id`, indexed at `position`.
+    pub fn remove(&mut self, id: EntityId, position: &Position) {
+        let key = bucket_of(position);
+        if let Some(bucket) = self.buckets.get_mut(&key) {
+            bucket.retain(|(other, _)| *other != id);
+            if bucket.is_empty() {
+                self.buckets.remove(&key);
+            }
+        }
+    }
+
+    /// Follows entity `id` from `from` to `to`.
+    pub fn relocate(&mut self, id: EntityId, from: &Position, to: &Position) {
+        if bucket_of(from) == bucket_of(to) {
+            let bucket = self.buckets.get_mut(&bucket_of(to)).into_iter().flatten();
+            for entry in bucket.filter(|(other, _)| *other == id) {
+                entry.1 = *to;
+            }
+        } else {
+            self.remove(id, from);
+            self.insert(id, to);
+        }
+    }
+
+    /// Entities standing at `position`.
+    pub fn at<'a>(&'a self, position: &'a Position) -> impl Iterator<Item = EntityId> + 'a {
+        self.buckets.get(&bucket_of(position)).into_iter().flatten()
+            .filter(move |(_, other)| other == position)
+            .map(|(id, _)| *id)
+    }
+
+    /// Entities at most `radius` away from `center`, sorted by ID.
+    pub fn within(&self, center: &Position, radius: i32) -> Vec<EntityId> {
+        let (left, top) = bucket_of(&Position::new(center.x() - radius, center.y() - radius));
+        let (right, bottom) = bucket_of(&Position::new(center.x() + radius, center.y() + radius));
+        let in_range = |position: &Position| center.distance_from(position) <= radius as f32;
+        let mut found: Vec<EntityId> = if ((right - left + 1) * (bottom - top + 1)) as usize > self.buckets.len() {
+            // Fewer buckets in use than in range, going through them all is cheaper
+            self.buckets.values().flatten().filter(|(_, position)| in_range(position)).map(|(id, _)| *id).collect()
+        } else {
+            (left..=right).flat_map(|x| (top..=bottom).map(move |y| (x, y)))
+                .flat_map(|key| self.buckets.get(&key).into_iter().flatten())
+                .filter(|(_, position)| in_range(position))
+                .map(|(id, _)| *id)
+                .collect()
+        };
+        found.sort_unstable();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::registry::EntityRegistry;
+
+    #[test]
+    fn entities_are_found_where_they_stand() {
+        let mut registry = EntityRegistry::new();
+        let (a, b) = (registry.spawn(), registry.spawn());
+        let mut index = SpatialIndex::default();
+        index.insert(a, &Position::new(1, 1));
+        index.insert(b, &Position::new(1, 1));
+        assert_eq!(index.at(&Position::new(1, 1)).collect::<Vec<_>>(), vec![a, b]);
+        assert_eq!(index.at(&Position::new(1, 2)).count(), 0);
+        index.remove(a, &Position::new(1, 1));
+        assert_eq!(index.at(&Position::new(1, 1)).collect::<Vec<_>>(), vec![b]);
+        index.remove(b, &Position::new(1, 1));
+        assert!(index.buckets.is_empty());
+    }
+
+    #[test]
+    fn entities_are_followed_within_and_across_buckets() {
+        let mut registry = EntityRegistry::new();
+        let id = registry.spawn();
+        let mut index = SpatialIndex::default();
+        index.insert(id, &Position::new(0, 0));
+        index.relocate(id, &Position::new(0, 0), &Position::new(BUCKET_SIZE - 1, 0));
+        assert_eq!(index.at(&Position::new(0, 0)).count(), 0);
+        assert_eq!(index.at(&Position::new(BUCKET_SIZE - 1, 0)).collect::<Vec<_>>(), vec![id]);
+        // Into the next bucket, and one across negative coordinates
+        index.relocate(id, &Position::new(BUCKET_SIZE - 1, 0), &Position::new(BUCKET_SIZE, 0));
+        index.relocate(id, &Position::new(BUCKET_SIZE, 0), &Position::new(-1, -1));
+        assert_eq!(index.at(&Position::new(-1, -1)).collect::<Vec<_>>(), vec![id]);
+        assert_eq!(index.buckets.len(), 1);
+    }
+
+    #[test]
+    fn range_queries_are_sorted_and_bounded() {
+        let mut registry = EntityRegistry::new();
+        let ids: Vec<EntityId> = (0..4).map(|_| registry.spawn()).collect();
+        let mut index = SpatialIndex::default();
+        // Inserted out of order, across several buckets
+        index.insert(ids[3], &Position::new(-5, 0));
+        index.insert(ids[1], &Position::new(20, 0));
+        index.insert(ids[0], &Position::new(3, 4));
+        index.insert(ids[2], &Position::new(0, 0));
+        let center = Position::new(0, 0);
+        assert_eq!(index.within(&center, 5), vec![ids[0], ids[2], ids[3]]);
+        assert_eq!(index.within(&center, 4), vec![ids[2]]);
+        assert_eq!(index.within(&center, 20), ids);
+        // Wide enough to take the path that goes through every bucket
+        assert_eq!(index.within(&center, 1000), ids);
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Serialize, Deserialize};
+
+/// Terminal colors, independent of the backend.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    /// Whatever the terminal uses when nothing is asked for.
+    Default
+}
+
+/// How text is emphasized.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[allow(missing_docs)]
+pub enum Style {
+    Normal,
+    Bold,
+    Underline,
+    /// Foreground and background colors swapped.
+    Reverse
+}
+
+/// Grid of character cells the game is drawn on.
+pub trait Surface {
+    /// Columns of cells.
+    fn width(&self) -> usize;
+    /// Rows of cells.
+    fn height(&self) -> usize;
+    /// Blanks every cell.
+    fn clear(&mut self);
+    /// Shows what was drawn since the last time.
+    fn present(&mut self);
+    /// Writes `text` from column `x` of row `y`, cutting off whatever doesn't fit.
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str);
+}
+
+/// A character on a [`MemorySurface`] with how it's drawn.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Cell {
+    /// What's drawn in the cell.
+    pub character: char,
+    /// How the character is emphasized.
+    pub style: Style,
+    /// Color of the character.
+    pub fg: Color,
+    /// Color behind the character.
+    pub bg: Color
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            character: ' ',
+            style: Style::Normal,
+            fg: Color::Default,
+            bg: Color::Default
+        }
+    }
+}
+
+/// In-memory grid of cells, useful to inspect what would be drawn without a terminal.
+pub struct MemorySurface {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    presented: usize
+}
+
+impl MemorySurface {
+    /// A blank surface of `width` columns and `height` rows.
+    pub fn new(width: usize, height: usize) -> MemorySurface {
+        MemorySurface {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            presented: 0
+        }
+    }
+
+    /// The cell at column `x` of row `y`, or `None` outside the surface.
+    pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x < self.width && y < self.height {
+            Some(&self.cells[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    /// Characters of a row, without styles or colors.
+    pub fn row(&self, y: usize) -> String {
+        if y >= self.height {
+            return String::new()
+        }
+        self.cells[y * self.width..(y + 1) * self.width].iter().map(|cell| cell.character).collect()
+    }
+
+    /// All rows joined by newlines.
+    pub fn contents(&self) -> String {
+        (0..self.height).map(|y| self.row(y)).collect::<Vec<String>>().join("\n")
+    }
+
+    /// How many times the surface has been presented.
+    pub fn present_count(&self) -> usize {
+        self.presented
+    }
+}
+
+impl Surface for MemorySurface {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn clear(&mut self) {
+        for cell in self.cells.iter_mut() {
+            *cell = Cell::default();
+        }
+    }
+
+    fn present(&mut self) {
+        self.presented += 1;
+    }
+
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str) {
+        if y >= self.height {
+            return
+        }
+        for (i, character) in text.chars().enumerate() {
+            if x + i >= self.width {
+                break
+            }
+            self.cells[y * self.width + x + i] = Cell { character, style, fg, bg };
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use super::Game;
+use super::animals;
+use super::components::{Ai, Energy, WantsToMove, ACTION_COST};
+use super::fov::FieldOfView;
+use super::map::GameMap;
+use super::registry::EntityId;
+use super::world::World;
+
+/// Lets time pass until the player can act again. Every entity with [`Energy`] gathers some as
+/// fast as its speed, then those with an [`Ai`] act in slot order for as long as they have
+/// enough, moving after every round of actions.
+pub fn schedule(game: &mut Game) {
+    pass_time(game);
+    // Entities spawned along the way wait for the next turn
+    let actors = game.world.ai.ids();
+    let mut first_round = true;
+    loop {
+        let ready: Vec<EntityId> = actors.iter().copied()
+            .filter(|&id| spend_action(&mut game.world, id, first_round))
+            .collect();
+        if ready.is_empty() {
+            break
+        }
+        ai(game, &ready);
+        movement(game);
+        first_round = false;
+    }
+}
+
+/// Energy `id` gathers every turn, from its dexterity.
+pub fn speed(world: &World, id: EntityId) -> i32 {
+    world.stats.get(id).map_or(1, |stats| stats.speed() as i32)
+}
+
+/// Lets the `actors` with an [`Ai`] look around and decide what to do, in order.
+pub fn ai(game: &mut Game, actors: &[EntityId]) {
+    for &id in actors {
+        // Entities removed by those that went before are skipped
+        let ai = match game.world.ai.get(id) {
+            Some(ai) => *ai,
+            None => continue
+        };
+        match ai {
+            Ai::Animal(state) => {
+                look_out_for_player(game, id);
+                animals::think(game, id, state);
+            }
+        }
+    }
+}
+
+/// Moves the entities that want to, in slot order, as long as nothing took their spot first.
+pub fn movement(game: &mut Game) {
+    for id in game.world.wants_to_move.ids() {
+        if let Some(WantsToMove(destination)) = game.world.wants_to_move.remove(id) {
+            if game.is_passable(&destination) {
+                game.world.set_position(id, destination);
+            }
+        }
+    }
+}
+
+// The player pays for the action it just took, then turns go by until it has enough energy for
+// the next one. It acts on every command, so it can't run short.
+fn pass_time(game: &mut Game) {
+    let player_speed = speed(&game.world, game.player);
+    let left = match game.world.energy.get_mut(game.player) {
+        Some(Energy(energy)) => {
+            *energy = (*energy - ACTION_COST).max(0);
+            *energy
+        },
+        None => 0
+    };
+    let turns = (ACTION_COST - left + player_speed - 1) / player_speed;
+    for id in game.world.energy.ids() {
+        let gained = turns * speed(&game.world, id);
+        if let Some(Energy(energy)) = game.world.energy.get_mut(id) {
+            *energy += gained;
+        }
+    }
+}
+
+// Takes the energy of an action from `id` if it has enough. Those without energy act once, in
+// the first round.
+fn spend_action(world: &mut World, id: EntityId, first_round: bool) -> bool {
+    match world.energy.get_mut(id) {
+        Some(Energy(energy)) if *energy >= ACTION_COST => {
+            *energy -= ACTION_COST;
+            true
+        },
+        Some(_) => false,
+        None => first_round && world.contains(id)
+    }
+}
+
+/// Casts the field of view of `id`, if it has vision and stands on the map.
+pub fn vision(world: &mut World, map: &GameMap, id: EntityId) {
+    let radius = world.stats.get(id).map_or(0, |stats| stats.per.lvl() as i32);
+    if let Some(&origin) = world.position(id) {
+        if let Some(vision) = world.vision.get_mut(id) {
+            vision.field_of_view = FieldOfView::compute(map, &origin, radius);
+        }
+    }
+}
+
+// Creatures only look out for the player, so there's no light to cast when it's out of range.
+fn look_out_for_player(game: &mut Game, id: EntityId) {
+    let radius = game.world.stats.get(id).map_or(0.0, |stats| stats.per.lvl() as f32);
+    let in_range = game.world.position(id).is_some_and(|position| position.distance_from(game.player_position()) < radius);
+    if in_range {
+        vision(&mut game.world, &game.map, id);
+    } else if let Some(vision) = game.world.vision.get_mut(id) {
+        vision.field_of_view = FieldOfView::default();
+    }
+}
+
+/// Takes the item `item` off the map into the inventory of `carrier`. Returns false if `item`
+/// isn't an item or `carrier` has nowhere to put it.
+pub fn pick_up(world: &mut World, carrier: EntityId, item: EntityId) -> bool {
+    if !world.items.contains(item) || !world.inventories.contains(carrier) {
+        return false
+    }
+    let mut components = match world.despawn(item) {
+        Some(components) => components,
+        None => return false
+    };
+    components.position = None;
+    if let Some(inventory) = world.inventories.get_mut(carrier) {
+        inventory.items.push(components);
+    }
+    true
+}
+
+/// Puts the item at `index` in the inventory of `carrier` down where it stands, returning the
+/// ID it gets back.
+pub fn drop_item(world: &mut World, carrier: EntityId, index: usize) -> Option<EntityId> {
+    let position = *world.position(carrier)?;
+    let inventory = world.inventories.get_mut(carrier)?;
+    if index >= inventory.items.len() {
+        return None
+    }
+    let item = inventory.items.remove(index);
+    Some(world.spawn(item.at(position)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::animals::AnimalState;
+    use super::super::command::Command;
+    use super::super::config::GameConfig;
+    use super::super::rpg::CharacterStats;
+    use super::super::world::EntityBuilder;
+
+    fn game() -> Game {
+        let config = GameConfig { seed: 3, map_size: Some(64), animals: 0, ..GameConfig::default() };
+        Game::new(&config).unwrap()
+    }
+
+    // Off the map, so it gathers energy and spends it without doing anything
+    fn actor(game: &mut Game, dex: usize) -> EntityId {
+        game.world.spawn(EntityBuilder::new()
+            .with_stats(CharacterStats::new(5, dex, 5))
+            .with_ai(Ai::Animal(AnimalState::Idle))
+            .with_energy())
+    }
+
+    fn energy(game: &Game, id: EntityId) -> i32 {
+        game.world.energy.get(id).unwrap().0
+    }
+
+    #[test]
+    fn entities_act_as_often_as_their_dexterity() {
+        let mut game = game();
+        let dexterities = [10, 5, 1, 0];
+        let actors: Vec<EntityId> = dexterities.iter().map(|&dex| actor(&mut game, dex)).collect();
+        let player_speed = speed(&game.world, game.player);
+        assert_eq!(player_speed, 5);
+        let player_turns = 20;
+        let mut actions = vec![0; actors.len()];
+        for _ in 0..player_turns {
+            let before: Vec<i32> = actors.iter().map(|&id| energy(&game, id)).collect();
+            game.step(Command::Wait);
+            // The player starts every turn without energy, so as many turns pass every time
+            let gained = ACTION_COST / player_speed;
+            for (i, &id) in actors.iter().enumerate() {
+                let spent = before[i] + gained * speed(&game.world, id) - energy(&game, id);
+                assert_eq!(spent % ACTION_COST, 0);
+                // Nothing is saved up for later
+                assert!(energy(&game, id) < ACTION_COST);
+                actions[i] += spent / ACTION_COST;
+            }
+        }
+        assert_eq!(game.tick_count(), player_turns);
+        // Twice the player's dexterity acts twice per player turn, a fifth once every five
+        assert_eq!(actions, vec![40, 20, 4, 4]);
+    }
+
+    #[test]
+    fn speed_is_at_least_one() {
+        let mut game = game();
+        let slow = actor(&mut game, 0);
+        let without_stats = game.world.spawn(EntityBuilder::new().with_energy());
+        assert_eq!(game.world.stats.get(slow).unwrap().speed(), 1);
+        assert_eq!(speed(&game.world, slow), 1);
+        assert_eq!(speed(&game.world, without_stats), 1);
+        // Even the slowest get to act eventually
+        for _ in 0..5 {
+            game.step(Command::Wait);
+        }
+        assert_eq!(energy(&game, slow), 0);
+        // Never spent, it has no Ai to act with
+        assert_eq!(energy(&game, without_stats), ACTION_COST);
+    }
+}
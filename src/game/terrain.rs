@@ -0,0 +1,133 @@
+use rand::Rng;
+use super::GameRng;
+use super::map::{Tile, TileType};
+use super::noise::Noise;
+
+/// Kind of land an area of the overworld is, deciding which tiles it's made of.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Biome {
+    /// Grass and dirt with the odd tree or boulder.
+    Plains,
+    /// Grass with trees all around.
+    Forest,
+    /// Sand, hot and dry.
+    Desert,
+    /// Mud and shallow water, wet and low.
+    Swamp,
+    /// Boulder-strewn slopes up to impassable rock peaks.
+    Mountains,
+    /// Water below the rest, deeper further from the shore.
+    Lake
+}
+
+impl Biome {
+    /// Name of the biome, like "Plains".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Biome::Plains => "Plains",
+            Biome::Forest => "Forest",
+            Biome::Desert => "Desert",
+            Biome::Swamp => "Swamp",
+            Biome::Mountains => "Mountains",
+            Biome::Lake => "Lake"
+        }
+    }
+}
+
+/// Elevation, moisture and temperature fields deciding the biome of every tile.
+pub struct Terrain {
+    elevation: Noise,
+    moisture: Noise,
+    temperature: Noise
+}
+
+impl Terrain {
+    /// Terrain with its fields seeded from `rng`.
+    pub fn new(rng: &mut GameRng) -> Terrain {
+        Terrain {
+            elevation: Noise::new(rng),
+            moisture: Noise::new(rng),
+            temperature: Noise::new(rng)
+        }
+    }
+
+    /// Height of the land at `x`, `y`, between 0 and 1.
+    pub fn elevation(&self, x: i32, y: i32) -> f32 {
+        self.elevation.fractal(x, y, 64.0, 5)
+    }
+
+    /// Which biome `x`, `y` belongs to.
+    pub fn biome_at(&self, x: i32, y: i32) -> Biome {
+        let elevation = self.elevation(x, y);
+        let moisture = self.moisture.fractal(x, y, 96.0, 3);
+        let temperature = self.temperature.fractal(x, y, 160.0, 2);
+        if elevation < 0.32 {
+            Biome::Lake
+        } else if elevation > 0.66 {
+            Biome::Mountains
+        } else if temperature > 0.56 && moisture < 0.45 {
+            Biome::Desert
+        } else if moisture > 0.58 && elevation < 0.42 {
+            Biome::Swamp
+        } else if moisture > 0.5 {
+            Biome::Forest
+        } else {
+            Biome::Plains
+        }
+    }
+
+    /// A tile for `x`, `y` picked at random among those of its biome.
+    pub fn tile_at(&self, x: i32, y: i32, rng: &mut GameRng) -> Tile {
+        let roll: f32 = rng.gen();
+        let tile_type = match self.biome_at(x, y) {
+            Biome::Plains => match roll {
+                r if r < 0.01 => TileType::Tree,
+                r if r < 0.015 => TileType::Boulder,
+                r if r < 0.2 => TileType::Dirt,
+                _ => TileType::Grass
+            },
+            Biome::Forest => match roll {
+                r if r < 0.3 => TileType::Tree,
+                r if r < 0.35 => TileType::Dirt,
+                _ => TileType::Grass
+            },
+            Biome::Desert => match roll {
+                r if r < 0.01 => TileType::Boulder,
+                r if r < 0.05 => TileType::Dirt,
+                _ => TileType::Sand
+            },
+            Biome::Swamp => match roll {
+                r if r < 0.05 => TileType::Tree,
+                r if r < 0.35 => TileType::ShallowWater,
+                r if r < 0.55 => TileType::Grass,
+                _ => TileType::Mud
+            },
+            Biome::Mountains => {
+                if self.elevation(x, y) > 0.72 {
+                    TileType::Rock
+                } else {
+                    match roll {
+                        r if r < 0.15 => TileType::Boulder,
+                        r if r < 0.3 => TileType::Grass,
+                        _ => TileType::Dirt
+                    }
+                }
+            },
+            Biome::Lake => {
+                if self.elevation(x, y) < 0.29 {
+                    TileType::DeepWater
+                } else {
+                    TileType::ShallowWater
+                }
+            }
+        };
+        let variant_chance = match tile_type {
+            TileType::Grass | TileType::Dirt => 0.2,
+            TileType::Sand => 0.1,
+            TileType::Mud => 0.3,
+            _ => 0.0
+        };
+        let variant = if rng.gen_bool(variant_chance) { rng.gen_range(1usize, 5) as u8 } else { 0 };
+        Tile::new(tile_type, variant)
+    }
+}
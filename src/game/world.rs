@@ -0,0 +1,461 @@
+use serde::{Serialize, Deserialize};
+use super::components::{Ai, Energy, Inventory, Item, Name, Vision, WantsToMove};
+use super::player::Player;
+use super::position::Position;
+use super::registry::{EntityId, EntityRegistry, SlotState};
+use super::render::RenderInfo;
+use super::rpg::CharacterStats;
+use super::spatial::SpatialIndex;
+
+/// One kind of component for every entity of a [`World`] that has it, by the slot of its ID.
+pub struct Storage<T> {
+    slots: Vec<Option<(EntityId, T)>>
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Storage { slots: Vec::new() }
+    }
+}
+
+impl<T> Storage<T> {
+    /// The component of `id`, if it has one.
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        match self.slots.get(id.index() as usize) {
+            Some(Some((owner, component))) if *owner == id => Some(component),
+            _ => None
+        }
+    }
+
+    /// The component of `id` to change, if it has one.
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        match self.slots.get_mut(id.index() as usize) {
+            Some(Some((owner, component))) if *owner == id => Some(component),
+            _ => None
+        }
+    }
+
+    /// Whether `id` has the component.
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Gives the component to `id`, replacing the one it had.
+    pub fn insert(&mut self, id: EntityId, component: T) {
+        let index = id.index() as usize;
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+        self.slots[index] = Some((id, component));
+    }
+
+    /// Takes the component away from `id`, returning it.
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        match self.slots.get_mut(id.index() as usize) {
+            Some(slot) if slot.as_ref().is_some_and(|(owner, _)| *owner == id) => slot.take().map(|(_, component)| component),
+            _ => None
+        }
+    }
+
+    /// Entities with this component, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.slots.iter().flatten().map(|(id, component)| (*id, component))
+    }
+
+    /// IDs of the entities with this component, for systems that change the world as they go.
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.iter().map(|(id, _)| id).collect()
+    }
+}
+
+/// The components of one entity outside of a [`World`]: put together before spawning it, and
+/// when it's saved or carried around.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EntityBuilder {
+    /// Where it stands, if it is on the map.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+    /// How it looks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub render_info: Option<RenderInfo>,
+    /// What it is called.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Name>,
+    /// What it is capable of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<CharacterStats>,
+    /// How it acts on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ai: Option<Ai>,
+    /// What it sees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<Vision>,
+    /// How close it is to its next action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy: Option<Energy>,
+    /// What it carries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory: Option<Inventory>,
+    /// Whether it can be picked up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<Item>,
+    /// Whether commands control it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player: Option<Player>
+}
+
+impl EntityBuilder {
+    /// An entity without any component.
+    pub fn new() -> EntityBuilder {
+        EntityBuilder::default()
+    }
+
+    /// Puts the entity at `position`.
+    pub fn at(mut self, position: Position) -> EntityBuilder {
+        self.position = Some(position);
+        self
+    }
+
+    /// Gives the entity a look.
+    pub fn with_render_info(mut self, render_info: RenderInfo) -> EntityBuilder {
+        self.render_info = Some(render_info);
+        self
+    }
+
+    /// Gives the entity a name.
+    pub fn with_name(mut self, name: Name) -> EntityBuilder {
+        self.name = Some(name);
+        self
+    }
+
+    /// Gives the entity stats.
+    pub fn with_stats(mut self, stats: CharacterStats) -> EntityBuilder {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Lets the entity act on its own.
+    pub fn with_ai(mut self, ai: Ai) -> EntityBuilder {
+        self.ai = Some(ai);
+        self
+    }
+
+    /// Lets the entity look around with a field of view.
+    pub fn with_vision(mut self) -> EntityBuilder {
+        self.vision = Some(Vision::default());
+        self
+    }
+
+    /// Lets the entity act as often as its speed allows, starting without energy.
+    pub fn with_energy(mut self) -> EntityBuilder {
+        self.energy = Some(Energy::default());
+        self
+    }
+
+    /// Lets the entity carry items, starting with none.
+    pub fn with_inventory(mut self) -> EntityBuilder {
+        self.inventory = Some(Inventory::default());
+        self
+    }
+
+    /// Makes the entity an item that can be picked up.
+    pub fn with_item(mut self) -> EntityBuilder {
+        self.item = Some(Item::default());
+        self
+    }
+
+    /// Makes the entity controlled by commands.
+    pub fn with_player(mut self) -> EntityBuilder {
+        self.player = Some(Player::default());
+        self
+    }
+}
+
+/// The entities of a level, each just an [`EntityId`] with whatever components it was given.
+/// Systems go through the storages of the components they care about.
+#[derive(Default)]
+pub struct World {
+    entities: EntityRegistry,
+    // Only changed through the world so the spatial index always agrees with it
+    positions: Storage<Position>,
+    spatial: SpatialIndex,
+    /// How entities look.
+    pub render_info: Storage<RenderInfo>,
+    /// What entities are called.
+    pub names: Storage<Name>,
+    /// What entities are capable of.
+    pub stats: Storage<CharacterStats>,
+    /// How entities act on their own.
+    pub ai: Storage<Ai>,
+    /// What entities see.
+    pub vision: Storage<Vision>,
+    /// How close entities are to their next action.
+    pub energy: Storage<Energy>,
+    /// What entities carry.
+    pub inventories: Storage<Inventory>,
+    /// Entities that can be picked up.
+    pub items: Storage<Item>,
+    /// Entities controlled by commands.
+    pub players: Storage<Player>,
+    /// Where entities decided to step this tick.
+    pub wants_to_move: Storage<WantsToMove>
+}
+
+impl World {
+    /// A world without entities.
+    pub fn new() -> World {
+        World::default()
+    }
+
+    /// Rebuilds a world from the generation and state of every slot and the components of the
+    /// entity in it, as listed by [`World::slots`]. Slots in use without components hold an
+    /// entity that has none.
+    pub fn from_slots(slots: Vec<(u32, SlotState, Option<EntityBuilder>)>) -> World {
+        let mut world = World {
+            entities: EntityRegistry::from_slots(slots.iter().map(|(generation, state, _)| (*generation, *state))),
+            ..World::default()
+        };
+        let alive = slots.into_iter().filter(|(_, state, _)| *state == SlotState::Alive);
+        for (id, (_, _, entity)) in world.entities.ids().into_iter().zip(alive) {
+            world.insert_components(id, entity.unwrap_or_default());
+        }
+        world
+    }
+
+    /// Generation and state of every slot and the components of the entity in it, if any.
+    pub fn slots(&self) -> Vec<(u32, SlotState, Option<EntityBuilder>)> {
+        let mut ids = self.entities.ids().into_iter();
+        self.entities.slots().map(|(generation, state)| {
+            let entity = if state == SlotState::Alive { ids.next().map(|id| self.components(id)) } else { None };
+            (generation, state, entity)
+        }).collect()
+    }
+
+    /// How many entities there are, the player included.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Whether there are no entities, not even the player.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Whether `id` is an entity of this world.
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.entities.contains(id)
+    }
+
+    /// Every entity, in slot order.
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.entities.ids()
+    }
+
+    /// Adds an entity with the given components.
+    pub fn spawn(&mut self, entity: EntityBuilder) -> EntityId {
+        let id = self.entities.spawn();
+        self.insert_components(id, entity);
+        id
+    }
+
+    /// Removes an entity, returning its components.
+    pub fn despawn(&mut self, id: EntityId) -> Option<EntityBuilder> {
+        if !self.entities.contains(id) {
+            return None
+        }
+        let entity = self.remove_components(id);
+        self.entities.remove(id);
+        Some(entity)
+    }
+
+    /// Takes an entity out with its components, like [`World::despawn`], but keeps its slot so
+    /// it can come back with the same ID, from here or another world.
+    pub fn take(&mut self, id: EntityId) -> Option<EntityBuilder> {
+        if !self.entities.contains(id) {
+            return None
+        }
+        let entity = self.remove_components(id);
+        self.entities.reserve(id);
+        Some(entity)
+    }
+
+    /// Sets the slot of `id` aside for it, so it can join this world later. Fails if another
+    /// entity has the slot.
+    pub fn reserve(&mut self, id: EntityId) -> bool {
+        self.entities.reserve(id)
+    }
+
+    /// Whether the slot of `id` is set aside for it while it's away.
+    pub fn is_reserved(&self, id: EntityId) -> bool {
+        self.entities.is_reserved(id)
+    }
+
+    /// Adds an entity taken out of this or another world under the ID it had. Fails, leaving
+    /// the world as it was, if another entity has the slot of `id`.
+    pub fn spawn_as(&mut self, id: EntityId, entity: EntityBuilder) -> bool {
+        if !self.entities.claim(id) {
+            return false
+        }
+        self.insert_components(id, entity);
+        true
+    }
+
+    fn remove_components(&mut self, id: EntityId) -> EntityBuilder {
+        self.wants_to_move.remove(id);
+        EntityBuilder {
+            position: self.remove_position(id),
+            render_info: self.render_info.remove(id),
+            name: self.names.remove(id),
+            stats: self.stats.remove(id),
+            ai: self.ai.remove(id),
+            vision: self.vision.remove(id),
+            energy: self.energy.remove(id),
+            inventory: self.inventories.remove(id),
+            item: self.items.remove(id),
+            player: self.players.remove(id)
+        }
+    }
+
+    /// A copy of every component of `id`.
+    pub fn components(&self, id: EntityId) -> EntityBuilder {
+        EntityBuilder {
+            position: self.positions.get(id).copied(),
+            render_info: self.render_info.get(id).copied(),
+            name: self.names.get(id).cloned(),
+            stats: self.stats.get(id).cloned(),
+            ai: self.ai.get(id).copied(),
+            vision: self.vision.get(id).cloned(),
+            energy: self.energy.get(id).copied(),
+            inventory: self.inventories.get(id).cloned(),
+            item: self.items.get(id).cloned(),
+            player: self.players.get(id).cloned()
+        }
+    }
+
+    /// Where `id` stands, if it's on the map.
+    pub fn position(&self, id: EntityId) -> Option<&Position> {
+        self.positions.get(id)
+    }
+
+    /// Entities standing somewhere, with where.
+    pub fn positions(&self) -> impl Iterator<Item = (EntityId, &Position)> {
+        self.positions.iter()
+    }
+
+    /// Puts `id` at `position`, whether it was on the map or not.
+    pub fn set_position(&mut self, id: EntityId, position: Position) {
+        if self.entities.contains(id) {
+            match self.positions.get(id) {
+                Some(previous) => self.spatial.relocate(id, previous, &position),
+                None => self.spatial.insert(id, &position)
+            }
+            self.positions.insert(id, position);
+        }
+    }
+
+    /// Takes `id` off the map, like an item being picked up.
+    pub fn remove_position(&mut self, id: EntityId) -> Option<Position> {
+        let position = self.positions.remove(id)?;
+        self.spatial.remove(id, &position);
+        Some(position)
+    }
+
+    /// Entities standing at `position`.
+    pub fn entities_at(&self, position: &Position) -> Vec<EntityId> {
+        self.spatial.at(position).collect()
+    }
+
+    /// Entities at most `radius` tiles away from `center`, in slot order.
+    pub fn entities_within(&self, center: &Position, radius: i32) -> Vec<EntityId> {
+        self.spatial.within(center, radius)
+    }
+
+    /// Whether something other than an item stands at `position`.
+    pub fn is_blocked(&self, position: &Position) -> bool {
+        self.spatial.at(position).any(|id| !self.items.contains(id))
+    }
+
+    /// Whether `id` sees `position`: within its field of view if it has [`Vision`], within
+    /// perception range otherwise.
+    pub fn can_see(&self, id: EntityId, position: &Position) -> bool {
+        match (self.vision.get(id), self.positions.get(id), self.stats.get(id)) {
+            (Some(vision), _, _) => vision.field_of_view.is_visible(position),
+            (None, Some(origin), Some(stats)) => origin.distance_from(position) < stats.per.lvl() as f32,
+            _ => false
+        }
+    }
+
+    fn insert_components(&mut self, id: EntityId, entity: EntityBuilder) {
+        if let Some(position) = entity.position {
+            self.set_position(id, position);
+        }
+        if let Some(render_info) = entity.render_info {
+            self.render_info.insert(id, render_info);
+        }
+        if let Some(name) = entity.name {
+            self.names.insert(id, name);
+        }
+        if let Some(stats) = entity.stats {
+            self.stats.insert(id, stats);
+        }
+        if let Some(ai) = entity.ai {
+            self.ai.insert(id, ai);
+        }
+        if let Some(vision) = entity.vision {
+            self.vision.insert(id, vision);
+        }
+        if let Some(energy) = entity.energy {
+            self.energy.insert(id, energy);
+        }
+        if let Some(inventory) = entity.inventory {
+            self.inventories.insert(id, inventory);
+        }
+        if let Some(item) = entity.item {
+            self.items.insert(id, item);
+        }
+        if let Some(player) = entity.player {
+            self.players.insert(id, player);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_spatial_index_follows_entities() {
+        let mut world = World::new();
+        let (start, next) = (Position::new(2, 2), Position::new(30, -4));
+        let walker = world.spawn(EntityBuilder::new().at(start));
+        let item = world.spawn(EntityBuilder::new().at(next).with_item());
+        assert_eq!(world.entities_at(&start), vec![walker]);
+        assert!(world.is_blocked(&start));
+        // Items don't block the way
+        assert!(!world.is_blocked(&next));
+
+        world.set_position(walker, next);
+        assert!(world.entities_at(&start).is_empty());
+        let mut here = world.entities_at(&next);
+        here.sort();
+        assert_eq!(here, vec![walker, item]);
+        assert_eq!(world.entities_within(&next, 1), vec![walker, item]);
+
+        assert_eq!(world.remove_position(item), Some(next));
+        assert_eq!(world.entities_at(&next), vec![walker]);
+        world.set_position(item, start);
+        assert_eq!(world.entities_at(&start), vec![item]);
+
+        let taken = world.take(walker).unwrap();
+        assert!(world.entities_at(&next).is_empty());
+        assert!(world.spawn_as(walker, taken));
+        assert_eq!(world.entities_at(&next), vec![walker]);
+
+        world.despawn(walker);
+        world.despawn(item);
+        assert!(world.entities_within(&start, 100).is_empty());
+        // Removed entities can't be put back on the map
+        world.set_position(walker, start);
+        assert!(world.entities_at(&start).is_empty());
+    }
+}
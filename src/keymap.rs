@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use rustbox::Key;
+use rust_game::Command;
+
+const COMMAND_NAMES: &[(&str, Command)] = &[
+    ("move_left", Command::Move(-1, 0)),
+    ("move_right", Command::Move(1, 0)),
+    ("move_up", Command::Move(0, -1)),
+    ("move_down", Command::Move(0, 1)),
+    ("move_up_left", Command::Move(-1, -1)),
+    ("move_up_right", Command::Move(1, -1)),
+    ("move_down_left", Command::Move(-1, 1)),
+    ("move_down_right", Command::Move(1, 1)),
+    ("wait", Command::Wait),
+    ("descend", Command::Descend),
+    ("ascend", Command::Ascend),
+    ("inspect", Command::ToggleInspect),
+    ("cancel", Command::Cancel),
+    ("quit", Command::Quit)
+];
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(io::Error),
+    Parse(String),
+    UnknownCommand(String),
+    UnknownKey(String),
+    Conflict { key: String, first: String, second: String }
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::Io(e) => write!(f, "could not read keybindings: {}", e),
+            KeymapError::Parse(e) => write!(f, "invalid keybindings file: {}", e),
+            KeymapError::UnknownCommand(name) => write!(f, "unknown command '{}'", name),
+            KeymapError::UnknownKey(name) => write!(f, "unknown key '{}'", name),
+            KeymapError::Conflict { key, first, second } => {
+                write!(f, "key '{}' is bound to both '{}' and '{}'", key, first, second)
+            }
+        }
+    }
+}
+
+pub struct Keymap {
+    bindings: HashMap<Key, Command>
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap { bindings: HashMap::new() }
+    }
+
+    pub fn config_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust-game").join("keys.toml"))
+    }
+
+    // Loads the keybindings file from the user config dir, if there is one.
+    pub fn load() -> Result<Keymap, KeymapError> {
+        match Keymap::config_path() {
+            Some(path) if path.exists() => Keymap::load_from(&path),
+            _ => Ok(Keymap::default())
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Keymap, KeymapError> {
+        let source = fs::read_to_string(path).map_err(KeymapError::Io)?;
+        Keymap::from_toml(&source)
+    }
+
+    // Commands listed in the file replace their default keys, the others keep them.
+    pub fn from_toml(source: &str) -> Result<Keymap, KeymapError> {
+        let table: BTreeMap<String, Vec<String>> = toml::from_str(source)
+            .map_err(|e| KeymapError::Parse(e.to_string()))?;
+        let mut listed = Vec::new();
+        let mut overrides = Vec::new();
+        for (name, keys) in &table {
+            let command = command_named(name).ok_or_else(|| KeymapError::UnknownCommand(name.clone()))?;
+            // Even with no keys at all, so a command can be unbound
+            listed.push(command);
+            for key_name in keys {
+                let key = parse_key(key_name).ok_or_else(|| KeymapError::UnknownKey(key_name.clone()))?;
+                overrides.push((key, key_name, command));
+            }
+        }
+        let mut keymap = Keymap::default();
+        keymap.bindings.retain(|_, command| !listed.contains(command));
+        for (key, key_name, command) in overrides {
+            if let Some(existing) = keymap.command_for(&key) {
+                if existing != command {
+                    return Err(KeymapError::Conflict {
+                        key: key_name.clone(),
+                        first: command_name(existing).to_string(),
+                        second: command_name(command).to_string()
+                    })
+                }
+            }
+            keymap.bind(key, command);
+        }
+        Ok(keymap)
+    }
+
+    pub fn bind(&mut self, key: Key, command: Command) {
+        self.bindings.insert(key, command);
+    }
+
+    pub fn command_for(&self, key: &Key) -> Option<Command> {
+        self.bindings.get(key).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap::new();
+        // Arrows and numpad with num lock off
+        keymap.bind(Key::Left, Command::Move(-1, 0));
+        keymap.bind(Key::Right, Command::Move(1, 0));
+        keymap.bind(Key::Up, Command::Move(0, -1));
+        keymap.bind(Key::Down, Command::Move(0, 1));
+        keymap.bind(Key::Home, Command::Move(-1, -1));
+        keymap.bind(Key::PageUp, Command::Move(1, -1));
+        keymap.bind(Key::End, Command::Move(-1, 1));
+        keymap.bind(Key::PageDown, Command::Move(1, 1));
+        // Vi-keys
+        keymap.bind(Key::Char('h'), Command::Move(-1, 0));
+        keymap.bind(Key::Char('l'), Command::Move(1, 0));
+        keymap.bind(Key::Char('k'), Command::Move(0, -1));
+        keymap.bind(Key::Char('j'), Command::Move(0, 1));
+        keymap.bind(Key::Char('y'), Command::Move(-1, -1));
+        keymap.bind(Key::Char('u'), Command::Move(1, -1));
+        keymap.bind(Key::Char('b'), Command::Move(-1, 1));
+        keymap.bind(Key::Char('n'), Command::Move(1, 1));
+        // Numpad with num lock on
+        keymap.bind(Key::Char('4'), Command::Move(-1, 0));
+        keymap.bind(Key::Char('6'), Command::Move(1, 0));
+        keymap.bind(Key::Char('8'), Command::Move(0, -1));
+        keymap.bind(Key::Char('2'), Command::Move(0, 1));
+        keymap.bind(Key::Char('7'), Command::Move(-1, -1));
+        keymap.bind(Key::Char('9'), Command::Move(1, -1));
+        keymap.bind(Key::Char('1'), Command::Move(-1, 1));
+        keymap.bind(Key::Char('3'), Command::Move(1, 1));
+        keymap.bind(Key::Char('5'), Command::Wait);
+        keymap.bind(Key::Char('.'), Command::Wait);
+        keymap.bind(Key::Char('>'), Command::Descend);
+        keymap.bind(Key::Char('<'), Command::Ascend);
+        keymap.bind(Key::Char('v'), Command::ToggleInspect);
+        keymap.bind(Key::Esc, Command::Cancel);
+        keymap
+    }
+}
+
+fn command_named(name: &str) -> Option<Command> {
+    COMMAND_NAMES.iter().find(|(n, _)| *n == name).map(|(_, command)| *command)
+}
+
+fn command_name(command: Command) -> &'static str {
+    COMMAND_NAMES.iter().find(|(_, c)| *c == command).map(|(name, _)| *name).unwrap_or("unknown")
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "Tab" => Key::Tab,
+        "Enter" => Key::Enter,
+        "Esc" => Key::Esc,
+        "Backspace" => Key::Backspace,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Delete" => Key::Delete,
+        "Insert" => Key::Insert,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Space" => Key::Char(' '),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Char(c),
+                _ => {
+                    if let Some(c) = name.strip_prefix("Ctrl-") {
+                        let mut chars = c.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) => Key::Ctrl(c.to_ascii_lowercase()),
+                            _ => return None
+                        }
+                    } else if let Some(n) = name.strip_prefix('F') {
+                        match n.parse::<u32>() {
+                            Ok(n) if (1..=12).contains(&n) => Key::F(n),
+                            _ => return None
+                        }
+                    } else {
+                        return None
+                    }
+                }
+            }
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_empty_file_keeps_the_defaults() {
+        let keymap = Keymap::from_toml("").unwrap();
+        assert_eq!(keymap.command_for(&Key::Char('h')), Some(Command::Move(-1, 0)));
+        assert_eq!(keymap.command_for(&Key::Left), Some(Command::Move(-1, 0)));
+        assert_eq!(keymap.command_for(&Key::Esc), Some(Command::Cancel));
+    }
+
+    #[test]
+    fn listed_commands_replace_their_default_keys() {
+        let keymap = Keymap::from_toml("move_left = [\"a\", \"Ctrl-B\"]\nquit = [\"q\"]").unwrap();
+        assert_eq!(keymap.command_for(&Key::Char('a')), Some(Command::Move(-1, 0)));
+        assert_eq!(keymap.command_for(&Key::Ctrl('b')), Some(Command::Move(-1, 0)));
+        assert_eq!(keymap.command_for(&Key::Char('h')), None);
+        assert_eq!(keymap.command_for(&Key::Left), None);
+        assert_eq!(keymap.command_for(&Key::Char('q')), Some(Command::Quit));
+        assert_eq!(keymap.command_for(&Key::Char('l')), Some(Command::Move(1, 0)));
+    }
+
+    #[test]
+    fn keys_freed_by_an_override_can_be_reused() {
+        let keymap = Keymap::from_toml("move_left = [\"a\"]\nwait = [\"h\"]").unwrap();
+        assert_eq!(keymap.command_for(&Key::Char('h')), Some(Command::Wait));
+        assert_eq!(keymap.command_for(&Key::Char('5')), None);
+    }
+
+    #[test]
+    fn commands_listed_without_keys_are_unbound() {
+        let keymap = Keymap::from_toml("wait = []").unwrap();
+        assert_eq!(keymap.command_for(&Key::Char('5')), None);
+        assert_eq!(keymap.command_for(&Key::Char('.')), None);
+        assert_eq!(keymap.command_for(&Key::Char('h')), Some(Command::Move(-1, 0)));
+    }
+
+    #[test]
+    fn a_key_bound_twice_is_a_conflict() {
+        match Keymap::from_toml("wait = [\"h\"]") {
+            Err(KeymapError::Conflict { key, first, second }) => {
+                assert_eq!((key.as_str(), first.as_str(), second.as_str()), ("h", "move_left", "wait"));
+            },
+            _ => panic!("expected a conflict with a default binding")
+        }
+        assert!(matches!(Keymap::from_toml("quit = [\"q\"]\nwait = [\"q\"]"), Err(KeymapError::Conflict { .. })));
+        assert!(Keymap::from_toml("quit = [\"q\", \"q\"]").is_ok());
+    }
+
+    #[test]
+    fn unknown_names_and_bad_files_are_errors() {
+        assert!(matches!(Keymap::from_toml("fly = [\"f\"]"), Err(KeymapError::UnknownCommand(name)) if name == "fly"));
+        assert!(matches!(Keymap::from_toml("quit = [\"F13\"]"), Err(KeymapError::UnknownKey(name)) if name == "F13"));
+        assert!(matches!(Keymap::from_toml("quit = [\"Ctrl-\"]"), Err(KeymapError::UnknownKey(_))));
+        assert!(matches!(Keymap::from_toml("quit = \"q\""), Err(KeymapError::Parse(_))));
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(parse_key("Space"), Some(Key::Char(' ')));
+        assert_eq!(parse_key("F12"), Some(Key::F(12)));
+        assert_eq!(parse_key("F0"), None);
+        assert_eq!(parse_key("Ctrl-X"), Some(Key::Ctrl('x')));
+        assert_eq!(parse_key("PageDown"), Some(Key::PageDown));
+        assert_eq!(parse_key("ab"), None);
+    }
+}
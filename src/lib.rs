@@ -0,0 +1,31 @@
+//! Simulation core of the roguelike, free of any terminal dependency.
+//!
+//! A [`Game`] is created from a [`GameConfig`], driven with [`Command`]s through [`Game::step`]
+//! and drawn on any [`Surface`] with [`render`]. The terminal frontend in `main.rs` is just one
+//! user of this API; map viewers, bots and balancing scripts can build on it the same way.
+
+#![warn(missing_docs)]
+
+mod game;
+
+pub use game::{Game, GameRng, GameState};
+pub use game::animals::{animal, AnimalState};
+pub use game::command::Command;
+pub use game::components::{Ai, Energy, Inventory, Item, Name, Vision, WantsToMove, ACTION_COST};
+pub use game::config::{ConfigError, GameConfig};
+pub use game::dungeon::{Dungeon, MapType, MIN_DUNGEON_SIZE};
+pub use game::level::{Level, Stairs};
+pub use game::fov::FieldOfView;
+pub use game::map::{GameMap, MapView, Tile, TileType, CHUNK_SIZE};
+pub use game::player::{player, Player};
+pub use game::pathfinding::{find_path, DijkstraMap};
+pub use game::position::Position;
+pub use game::prefab::{Orientation, Prefab, PrefabCell};
+pub use game::render::{render, RenderInfo};
+pub use game::registry::{EntityId, SlotState};
+pub use game::replay::{Replay, ReplayError, ReplayEvent};
+pub use game::rpg::{CharacterStat, CharacterStats};
+pub use game::save::SaveError;
+pub use game::surface::{Cell, Color, MemorySurface, Style, Surface};
+pub use game::terrain::{Biome, Terrain};
+pub use game::world::{EntityBuilder, Storage, World};
@@ -1,8 +1,167 @@
 extern crate rustbox;
 
-mod game;
+mod keymap;
+mod terminal;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use clap::{App, Arg, ArgMatches};
+use rust_game::{Game, GameConfig, Replay};
+
+struct Options {
+    config: GameConfig,
+    save_path: Option<PathBuf>,
+    new_game: bool,
+    record_path: Option<PathBuf>,
+    replay_path: Option<PathBuf>,
+    headless: bool,
+    replay_speed: f64
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    process::exit(1);
+}
+
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str, default: T) -> T {
+    match matches.value_of(name) {
+        None => default,
+        Some(value) => match value.parse() {
+            Ok(value) => value,
+            Err(_) => exit_with_error(&format!("invalid value '{}' for --{}", value, name))
+        }
+    }
+}
+
+fn default_save_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("rust-game").join("save.json"))
+}
+
+fn parse_options() -> Options {
+    let matches = App::new("rust-game")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Roguelike game in the terminal")
+        .arg(Arg::with_name("seed").long("seed").takes_value(true).value_name("NUMBER")
+            .help("Seed for the world generation and simulation"))
+        .arg(Arg::with_name("map-size").long("map-size").takes_value(true).value_name("TILES")
+            .help("Width and height of the map [default: unbounded]"))
+        .arg(Arg::with_name("map-type").long("map-type").takes_value(true).value_name("TYPE")
+            .possible_values(&["overworld", "rooms", "caves", "tunnels"])
+            .help("Generator for the map [default: overworld]"))
+        .arg(Arg::with_name("animals").long("animals").takes_value(true).value_name("COUNT")
+            .help("Number of animals to spawn"))
+        .arg(Arg::with_name("spawn-radius").long("spawn-radius").takes_value(true).value_name("TILES")
+            .help("Maximum distance from the map center where animals spawn"))
+        .arg(Arg::with_name("save").long("save").takes_value(true).value_name("FILE")
+            .help("Save file to resume from and to write when quitting"))
+        .arg(Arg::with_name("new").long("new")
+            .help("Start a new game even if a save file exists"))
+        .arg(Arg::with_name("record").long("record").takes_value(true).value_name("FILE")
+            .help("Record the commands of a new game to a replay file, without saving unless --save is given"))
+        .arg(Arg::with_name("replay").long("replay").takes_value(true).value_name("FILE")
+            .conflicts_with("record")
+            .help("Play back a replay file and check that it ends in the recorded state"))
+        .arg(Arg::with_name("headless").long("headless").requires("replay")
+            .help("Play the replay without a terminal, as fast as possible"))
+        .arg(Arg::with_name("replay-speed").long("replay-speed").takes_value(true).value_name("STEPS")
+            .requires("replay")
+            .help("Commands per second when showing a replay [default: 10]"))
+        .get_matches();
+    let defaults = GameConfig::default();
+    let config = GameConfig {
+        seed: parse_arg(&matches, "seed", defaults.seed),
+        map_size: if matches.is_present("map-size") { Some(parse_arg(&matches, "map-size", 0)) } else { defaults.map_size },
+        map_type: parse_arg(&matches, "map-type", defaults.map_type),
+        animals: parse_arg(&matches, "animals", defaults.animals),
+        spawn_radius: parse_arg(&matches, "spawn-radius", defaults.spawn_radius)
+    };
+    if let Err(e) = config.validate() {
+        exit_with_error(&e.to_string());
+    }
+    let replay_speed = parse_arg(&matches, "replay-speed", 10.0);
+    if replay_speed <= 0.0 {
+        exit_with_error("--replay-speed must be greater than zero");
+    }
+    let recording = matches.is_present("record");
+    let save_path = matches.value_of("save").map(PathBuf::from);
+    Options {
+        config,
+        // A recording is a throwaway game, so it only saves where it's explicitly told to
+        save_path: if recording { save_path } else { save_path.or_else(default_save_path) },
+        // Replays start from the config, so a recording can't resume a saved game
+        new_game: matches.is_present("new") || recording,
+        record_path: matches.value_of("record").map(PathBuf::from),
+        replay_path: matches.value_of("replay").map(PathBuf::from),
+        headless: matches.is_present("headless"),
+        replay_speed
+    }
+}
+
+fn run_replay(path: &Path, options: &Options) {
+    let replay = match Replay::load(path) {
+        Ok(replay) => replay,
+        Err(e) => exit_with_error(&e.to_string())
+    };
+    let mut game = match replay.start() {
+        Ok(game) => game,
+        Err(e) => exit_with_error(&e.to_string())
+    };
+    let result = if options.headless {
+        replay.play(&mut game, |_| true)
+    } else {
+        let mut terminal = terminal::Terminal::new(keymap::Keymap::default());
+        terminal.play_replay(&mut game, &replay, options.replay_speed)
+    };
+    match result {
+        Ok(hash) => println!("Replay finished at tick {} with state hash {:016x}", game.tick_count(), hash),
+        Err(e) => exit_with_error(&e.to_string())
+    }
+}
 
 fn main() {
-    let mut game = game::Game::new();
-    game.run();
+    let options = parse_options();
+    if let Some(path) = &options.replay_path {
+        run_replay(path, &options);
+        return
+    }
+    let keymap = match keymap::Keymap::load() {
+        Ok(keymap) => keymap,
+        Err(e) => exit_with_error(&e.to_string())
+    };
+    let mut game = match &options.save_path {
+        Some(path) if path.exists() && !options.new_game => match Game::load(path) {
+            Ok(game) => game,
+            Err(e) => exit_with_error(&format!("{} (start with --new to ignore it)", e))
+        },
+        _ => Game::new(&options.config).unwrap_or_else(|e| exit_with_error(&e.to_string()))
+    };
+    if options.record_path.is_some() {
+        game.start_recording(Replay::new(&options.config));
+    }
+    // The terminal is restored before anything is written, even on a crash
+    let session = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut terminal = terminal::Terminal::new(keymap);
+        terminal.run(&mut game);
+    }));
+    if let Err(crash) = session {
+        // Keep the commands that led to the crash so it can be reproduced
+        if let (Some(path), Some(replay)) = (&options.record_path, game.abandon_recording()) {
+            if let Err(e) = replay.save(path) {
+                eprintln!("Error: {}", e);
+            }
+        }
+        panic::resume_unwind(crash);
+    }
+    if let (Some(path), Some(replay)) = (&options.record_path, game.finish_recording()) {
+        if let Err(e) = replay.save(path) {
+            exit_with_error(&e.to_string());
+        }
+    }
+    if let Some(path) = &options.save_path {
+        if let Err(e) = game.save(path) {
+            exit_with_error(&e.to_string());
+        }
+    }
 }
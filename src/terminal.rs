@@ -0,0 +1,90 @@
+use std::time::Duration;
+use rustbox::{Key, RustBox};
+use rust_game::{render, Color, Game, GameState, Replay, ReplayError, Style, Surface};
+use crate::keymap::Keymap;
+
+pub struct Terminal {
+    rustbox: RustBox,
+    keymap: Keymap
+}
+
+impl Terminal {
+    pub fn new(keymap: Keymap) -> Terminal {
+        let rustbox = match RustBox::init(Default::default()) {
+            Result::Ok(v) => v,
+            Result::Err(e) => panic!("Failed: {}", e),
+        };
+        Terminal { rustbox, keymap }
+    }
+
+    pub fn run(&mut self, game: &mut Game) {
+        while game.current_state() != GameState::Quit {
+            render(self, game);
+            match self.rustbox.poll_event(false) {
+                Ok(rustbox::Event::KeyEvent(key)) => {
+                    if let Some(command) = self.keymap.command_for(&key) {
+                        game.step(command);
+                    }
+                },
+                Err(e) => panic!("{}", e),
+                _ => {}
+            }
+        }
+    }
+
+    // Shows a replay at the given pace, `Esc` stops it.
+    pub fn play_replay(&mut self, game: &mut Game, replay: &Replay, steps_per_second: f64) -> Result<u64, ReplayError> {
+        let delay = Duration::from_secs_f64(1.0 / steps_per_second);
+        let result = replay.play(game, |game| {
+            render(self, game);
+            !matches!(self.rustbox.peek_event(delay, false), Ok(rustbox::Event::KeyEvent(Key::Esc)))
+        });
+        render(self, game);
+        result
+    }
+}
+
+fn rustbox_color(color: Color) -> rustbox::Color {
+    match color {
+        Color::Black => rustbox::Color::Black,
+        Color::Red => rustbox::Color::Red,
+        Color::Green => rustbox::Color::Green,
+        Color::Yellow => rustbox::Color::Yellow,
+        Color::Blue => rustbox::Color::Blue,
+        Color::Magenta => rustbox::Color::Magenta,
+        Color::Cyan => rustbox::Color::Cyan,
+        Color::White => rustbox::Color::White,
+        Color::Default => rustbox::Color::Default
+    }
+}
+
+fn rustbox_style(style: Style) -> rustbox::Style {
+    match style {
+        Style::Normal => rustbox::RB_NORMAL,
+        Style::Bold => rustbox::RB_BOLD,
+        Style::Underline => rustbox::RB_UNDERLINE,
+        Style::Reverse => rustbox::RB_REVERSE
+    }
+}
+
+impl Surface for Terminal {
+    fn width(&self) -> usize {
+        self.rustbox.width()
+    }
+
+    fn height(&self) -> usize {
+        self.rustbox.height()
+    }
+
+    fn clear(&mut self) {
+        self.rustbox.clear();
+    }
+
+    fn present(&mut self) {
+        self.rustbox.present();
+    }
+
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str) {
+        self.rustbox.print(x, y, rustbox_style(style), rustbox_color(fg), rustbox_color(bg), text);
+    }
+}